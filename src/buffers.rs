@@ -1,5 +1,4 @@
-use image::{DynamicImage, ImageBuffer, Rgb, Rgba, RgbImage, Rgb32FImage, Rgba32FImage};
-use std::sync::{Arc, Mutex, RwLock};
+use image::{ImageBuffer, Rgba, Rgba32FImage};
 use crate::color::Color;
 use std::ops;
 
@@ -8,14 +7,25 @@ pub struct FrameBuffers {
     pub rgba: Rgba32FImage,
     pub diffuse: Rgba32FImage,
     pub specular: Rgba32FImage,
+    pub clearcoat: Rgba32FImage,
+    pub albedo: Rgba32FImage,
+    pub normal: Rgba32FImage,
+    pub rgba_even: Rgba32FImage,
+    pub rgba_odd: Rgba32FImage,
 }
 
 impl FrameBuffers {
     pub fn new(rgba: Rgba32FImage, diffuse: Rgba32FImage, specular: Rgba32FImage) -> Self {
+        let (width, height) = rgba.dimensions();
         Self {
             rgba,
             diffuse,
-            specular
+            specular,
+            clearcoat: ImageBuffer::new(width, height),
+            albedo: ImageBuffer::new(width, height),
+            normal: ImageBuffer::new(width, height),
+            rgba_even: ImageBuffer::new(width, height),
+            rgba_odd: ImageBuffer::new(width, height),
         }
     }
 
@@ -41,15 +51,39 @@ impl FrameBuffers {
             specular[2] as f64, 
             specular[3] as f64
         );
+        let clearcoat = self.clearcoat.get_pixel(x, y);
+        let clearcoat = Color::new(
+            clearcoat[0] as f64, 
+            clearcoat[1] as f64, 
+            clearcoat[2] as f64, 
+            clearcoat[3] as f64
+        );
+        let albedo = self.albedo.get_pixel(x, y);
+        let albedo = Color::new(
+            albedo[0] as f64, 
+            albedo[1] as f64, 
+            albedo[2] as f64, 
+            albedo[3] as f64
+        );
+        let normal = self.normal.get_pixel(x, y);
+        let normal = Color::new(
+            normal[0] as f64, 
+            normal[1] as f64, 
+            normal[2] as f64, 
+            normal[3] as f64
+        );
         Lobes::new(
             rgba,
             diffuse,
             specular,
+            clearcoat,
             Color::black(),
+            albedo,
+            normal,
             )
     }
 
-    pub fn put_pixel(&mut self, x: u32, y: u32, rgba: Color, diffuse: Color, specular: Color) -> () {
+    pub fn put_pixel(&mut self, x: u32, y: u32, rgba: Color, diffuse: Color, specular: Color, clearcoat: Color) {
         self.rgba.put_pixel(x, y, 
             Rgba([
                 rgba.r as f32, 
@@ -71,6 +105,44 @@ impl FrameBuffers {
                 specular.b as f32, 
                 specular.a as f32
             ]));
+        self.clearcoat.put_pixel(x, y,             
+            Rgba([
+                clearcoat.r as f32, 
+                clearcoat.g as f32, 
+                clearcoat.b as f32, 
+                clearcoat.a as f32
+            ]));
+    }
+
+    pub fn put_features(&mut self, x: u32, y: u32, albedo: Color, normal: Color) {
+        self.albedo.put_pixel(x, y, 
+            Rgba([
+                albedo.r as f32, 
+                albedo.g as f32, 
+                albedo.b as f32, 
+                albedo.a as f32
+                ]));
+        self.normal.put_pixel(x, y, 
+            Rgba([
+                normal.r as f32, 
+                normal.g as f32, 
+                normal.b as f32, 
+                normal.a as f32
+                ]));
+    }
+
+    // accumulate even and odd samples separately so the denoiser can estimate variance
+    pub fn put_split_sample(&mut self, x: u32, y: u32, sample: u16, rgba: Color) {
+        let half = if sample.is_multiple_of(2) {&mut self.rgba_even} else {&mut self.rgba_odd};
+        let count = (sample / 2) as f32;
+        let previous = *half.get_pixel(x, y);
+        half.put_pixel(x, y, 
+            Rgba([
+                (rgba.r as f32 + previous[0] * count) / (count + 1.0), 
+                (rgba.g as f32 + previous[1] * count) / (count + 1.0), 
+                (rgba.b as f32 + previous[2] * count) / (count + 1.0), 
+                (rgba.a as f32 + previous[3] * count) / (count + 1.0)
+                ]));
     }
 }
 
@@ -80,16 +152,22 @@ pub struct Lobes {
     pub rgba: Color,
     pub diffuse: Color,
     pub specular: Color, 
+    pub clearcoat: Color,
     pub emission: Color,
+    pub albedo: Color,
+    pub normal: Color,
 }
 
 impl Lobes {
-    pub fn new(rgba: Color, diffuse: Color, specular: Color, emission: Color) -> Self {
+    pub fn new(rgba: Color, diffuse: Color, specular: Color, clearcoat: Color, emission: Color, albedo: Color, normal: Color) -> Self {
         Lobes {
             rgba,
             diffuse,
             specular,
+            clearcoat,
             emission,
+            albedo,
+            normal,
         }
     }
 
@@ -98,16 +176,10 @@ impl Lobes {
             rgba: Color::black(),
             diffuse: Color::black(),
             specular: Color::black(),
+            clearcoat: Color::black(),
             emission: Color::black(),
-        }
-    }
-
-    pub fn average_samples(&self, sample: f64, average: f64, color: Lobes) -> Lobes {
-        Lobes{
-            rgba: (color.rgba + (self.rgba * sample)) / average,
-            diffuse: (color.rgba + (self.rgba * sample)) / average,
-            specular: (color.rgba + (self.rgba * sample)) / average,
-            emission: self.emission,
+            albedo: Color::black(),
+            normal: Color::black(),
         }
     }
 }
@@ -119,7 +191,10 @@ impl ops::Add for Lobes {
             rgba: self.rgba + other.rgba,
             diffuse: self.diffuse + other.diffuse,
             specular: self.specular + other.specular,
+            clearcoat: self.clearcoat + other.clearcoat,
             emission: self.emission + other.emission,
+            albedo: self.albedo + other.albedo,
+            normal: self.normal + other.normal,
         }
     }
 }
@@ -108,6 +108,21 @@ impl Bvh {
         }
     }
 
+    pub fn hit_filtered(&self, r: &Ray, t_min: f64, t_max: f64, object_id: usize) -> (bool, Option<HitRecord>) {
+        if let (false, None) = self.bbox.hit(r, t_min, t_max) {
+            return (false, None)
+        }
+        match self.left.hit_filtered(r, t_min, t_max, object_id) {
+            (true, Some(left_hit_rec)) => {
+                match self.right.hit_filtered(r, t_min, left_hit_rec.t, object_id) {
+                    (true, Some(right_hit_rec)) => (true, Some(right_hit_rec)),
+                    _ => (true, Some(left_hit_rec)),
+                }
+            },
+            _ => self.right.hit_filtered(r, t_min, t_max, object_id),
+        }
+    }
+
     pub fn bounding_box(&self, time0: f64, time1: f64) -> Aabb {
         self.bbox
     }
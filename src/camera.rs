@@ -63,6 +63,11 @@ impl Camera {
         }
     }
 
+    // angle a pixel subtends, the starting spread of camera ray cones
+    pub fn pixel_spread(&self, image_height: u32) -> f64 {
+        2.0 * f64::tan(degrees_to_radians(self.fov) / 2.0) / image_height as f64
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let rd = Vec3::random_in_unit_disk() * (self.aperature / 2.0);
         let offset = self.u * rd.x() + self.v * rd.y();
@@ -1,6 +1,6 @@
-use std::cmp::Ordering;
 use crate::vec3::Vec3;
 use crate::mat3::Mat3;
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
@@ -23,26 +23,12 @@ impl Color {
         Self::new(1.0, 1.0, 1.0, 1.0)
     }
 
-    pub fn gray() -> Self {
-        Self::new(0.5, 0.5, 0.5, 1.0)
-    }
-
-    pub fn green() -> Self {
-        Self::new(0.0, 1.0, 0.0, 1.0)
-    }
-
     pub fn sum(&self) -> f64 {
         self.r + self.g + self.b
     }
 
     pub fn max(&self) -> f64 {
-        if self.r > self.g && self.r > self.b{
-            return self.r
-        } else if self.g > self.r && self.g > self.b {
-            return self.g
-        } else {
-            return self.b
-        }
+        self.r.max(self.g).max(self.b)
     }
 
     pub fn has_nan(&self) -> bool {
@@ -52,13 +38,26 @@ impl Color {
         false
     }
 
-    pub fn to_normal_vec(&self, tangent: Vec3, bitangent: Vec3, surface_normal: Vec3) -> Vec3 {
+    // linear rgb of a blackbody normalized to its spectral peak, tabulated up to 12000k
+    pub fn blackbody(kelvin: f64) -> Self {
+        let table = BLACKBODY.get_or_init(|| {
+            (0..=BLACKBODY_STEPS).map(|i| blackbody_rgb(i as f64 * BLACKBODY_MAX / BLACKBODY_STEPS as f64)).collect()
+        });
+        let x = (kelvin / BLACKBODY_MAX).clamp(0.0, 1.0) * BLACKBODY_STEPS as f64;
+        let i = (x as usize).min(BLACKBODY_STEPS - 1);
+        let f = x - i as f64;
+        let rgb = [0, 1, 2].map(|c| table[i][c] * (1.0 - f) + table[i + 1][c] * f);
+        Self::new(rgb[0], rgb[1], rgb[2], 1.0)
+    }
+
+    // strength scales the tangential part, a flat or degenerate map gives back the surface normal
+    pub fn to_normal_vec(self, tangent: Vec3, bitangent: Vec3, surface_normal: Vec3, strength: f64) -> Vec3 {
         let x = self.r;
         let y = self.g;
         let z = self.b;
 
         // remap from [0, 1] to [-1, 1]
-        let tangent_normal = Vec3::new(x * 2.0 - 1.0, y * 2.0 - 1.0, z * 2.0 - 1.0);
+        let tangent_normal = Vec3::new((x * 2.0 - 1.0) * strength, (y * 2.0 - 1.0) * strength, z * 2.0 - 1.0);
 
         // transformation matrix
         let tangent_matrix = Mat3::new([
@@ -69,8 +68,11 @@ impl Color {
 
         // transform to world space
         let world_normal = tangent_matrix * tangent_normal;
+        if world_normal.length_squared() < 1e-12 {
+            return surface_normal
+        }
 
-        return world_normal
+        world_normal.normalize()
     }
 }
 
@@ -176,4 +178,45 @@ impl std::ops::Div<Color> for Color {
             a: self.a / other.a
         }
     }
-}
\ No newline at end of file
+}
+const BLACKBODY_MAX: f64 = 12000.0;
+const BLACKBODY_STEPS: usize = 240;
+static BLACKBODY: OnceLock<Vec<[f64; 3]>> = OnceLock::new();
+
+// planck's law integrated against the wyman et al. 2013 fit of the cie 1931 observer
+fn blackbody_rgb(kelvin: f64) -> [f64; 3] {
+    if kelvin < 1.0 {
+        return [0.0; 3]
+    }
+    let planck = |lambda_nm: f64| {
+        let (c, h, kb) = (299792458.0, 6.62606957e-34, 1.3806488e-23);
+        let l = lambda_nm * 1e-9;
+        (2.0 * h * c * c) / (l.powi(5) * (((h * c) / (l * kb * kelvin)).exp() - 1.0))
+    };
+    let gaussian = |x: f64, mu: f64, sigma_low: f64, sigma_high: f64| {
+        let t = (x - mu) / if x < mu {sigma_low} else {sigma_high};
+        (-0.5 * t * t).exp()
+    };
+    let peak = planck(2.8977721e-3 / kelvin * 1e9);
+
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    let mut y_norm = 0.0;
+    for step in 0..=80 {
+        let lambda = 380.0 + step as f64 * 5.0;
+        let xb = 1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2);
+        let yb = 0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1);
+        let zb = 1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8);
+        let radiance = planck(lambda) / peak;
+        x += xb * radiance;
+        y += yb * radiance;
+        z += zb * radiance;
+        y_norm += yb;
+    }
+    let (x, y, z) = (x / y_norm, y / y_norm, z / y_norm);
+    [
+        f64::max(3.2404542 * x - 1.5371385 * y - 0.4985314 * z, 0.0),
+        f64::max(-0.9692660 * x + 1.8760108 * y + 0.0415560 * z, 0.0),
+        f64::max(0.0556434 * x - 0.2040259 * y + 1.0572252 * z, 0.0),
+    ]
+}
@@ -0,0 +1,136 @@
+use image::{ImageBuffer, Rgba, Rgba32FImage};
+use crate::buffers::FrameBuffers;
+use rayon::prelude::*;
+
+
+// joint non-local means filter guided by first-hit albedo and normals,
+// color distances are normalized by the variance of the split sample buffers
+pub struct Denoiser {
+    pub radius: i32,
+    pub patch_radius: i32,
+    pub strength: f32,
+    pub albedo_sigma: f32,
+    pub normal_sigma: f32,
+}
+
+impl Denoiser {
+    pub fn new(radius: i32, patch_radius: i32, strength: f32, albedo_sigma: f32, normal_sigma: f32) -> Self {
+        Self {
+            radius,
+            patch_radius,
+            strength,
+            albedo_sigma,
+            normal_sigma,
+        }
+    }
+
+    pub fn default() -> Self {
+        Self {
+            radius: 8,
+            patch_radius: 1,
+            strength: 0.45,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.3,
+        }
+    }
+
+    pub fn denoise(&self, buffers: &FrameBuffers) -> Rgba32FImage {
+        let (width, height) = buffers.rgba.dimensions();
+        let (w, h) = (width as i32, height as i32);
+        let idx = |x: i32, y: i32| (y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize;
+
+        // demodulate albedo so texture detail survives the filter
+        let mut color = vec![[0.0f32; 3]; (width * height) as usize];
+        let mut variance = vec![[0.0f32; 3]; (width * height) as usize];
+        let mut albedo = vec![[0.0f32; 3]; (width * height) as usize];
+        let mut normal = vec![[0.0f32; 3]; (width * height) as usize];
+        for (x, y, pixel) in buffers.rgba.enumerate_pixels() {
+            let i = (y * width + x) as usize;
+            let a = buffers.albedo.get_pixel(x, y);
+            let n = buffers.normal.get_pixel(x, y);
+            let even = buffers.rgba_even.get_pixel(x, y);
+            let odd = buffers.rgba_odd.get_pixel(x, y);
+            for c in 0..3 {
+                let demodulate = if a[3] > 0.0 {a[c].max(0.01)} else {1.0};
+                color[i][c] = pixel[c] / demodulate;
+                let diff = (even[c] - odd[c]) / demodulate;
+                variance[i][c] = diff * diff * 0.25;
+                albedo[i][c] = a[c];
+                normal[i][c] = n[c];
+            }
+        }
+
+        let filtered = (0..h).into_par_iter().flat_map(|y| {
+            let mut row = Vec::with_capacity(width as usize * 4);
+            for x in 0..w {
+                let p = idx(x, y);
+                let mut sum = [0.0f32; 3];
+                let mut weight_sum = 0.0f32;
+                for dy in -self.radius..=self.radius {
+                    for dx in -self.radius..=self.radius {
+                        let q = idx(x + dx, y + dy);
+                        let weight = self.color_weight(&color, &variance, &idx, (x, y), (dx, dy))
+                            * feature_weight(&albedo[p], &albedo[q], self.albedo_sigma)
+                            * feature_weight(&normal[p], &normal[q], self.normal_sigma);
+                        for c in 0..3 {
+                            sum[c] += color[q][c] * weight;
+                        }
+                        weight_sum += weight;
+                    }
+                }
+
+                // remodulate and keep the raw alpha
+                let a = buffers.albedo.get_pixel(x as u32, y as u32);
+                let alpha = buffers.rgba.get_pixel(x as u32, y as u32)[3];
+                for c in 0..3 {
+                    let remodulate = if a[3] > 0.0 {a[c].max(0.01)} else {1.0};
+                    row.push(sum[c] / weight_sum * remodulate);
+                }
+                row.push(alpha);
+            }
+            row
+        }).collect::<Vec<f32>>();
+
+        let mut output: Rgba32FImage = ImageBuffer::from_raw(width, height, filtered).unwrap();
+        for pixel in output.pixels_mut() {
+            if pixel.0.iter().any(|c| c.is_nan()) {
+                *pixel = Rgba([0.0, 0.0, 0.0, pixel[3]]);
+            }
+        }
+        output
+    }
+
+    fn color_weight(
+        &self,
+        color: &[[f32; 3]],
+        variance: &[[f32; 3]],
+        idx: &dyn Fn(i32, i32) -> usize,
+        (x, y): (i32, i32),
+        (dx, dy): (i32, i32)
+        ) -> f32 {
+        // patch distance from Rousselle et al. 2012, variance cancellation included
+        let k2 = self.strength * self.strength;
+        let mut distance = 0.0;
+        let mut count = 0.0;
+        for py in -self.patch_radius..=self.patch_radius {
+            for px in -self.patch_radius..=self.patch_radius {
+                let p = idx(x + px, y + py);
+                let q = idx(x + dx + px, y + dy + py);
+                for c in 0..3 {
+                    let var_p = variance[p][c];
+                    let var_q = variance[q][c];
+                    let diff = color[p][c] - color[q][c];
+                    distance += (diff * diff - (var_p + var_p.min(var_q)))
+                        / (1e-4 + k2 * (var_p + var_q));
+                }
+                count += 3.0;
+            }
+        }
+        (-f32::max(distance / count, 0.0)).exp()
+    }
+}
+
+fn feature_weight(a: &[f32; 3], b: &[f32; 3], sigma: f32) -> f32 {
+    let distance = (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2);
+    (-distance / (2.0 * sigma * sigma)).exp()
+}
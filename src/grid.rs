@@ -0,0 +1,280 @@
+use crate::vec3::Vec3;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+
+// sparse grids store 8^3 voxel bricks, the majorant grid uses the same cell size
+const BRICK_SIZE: usize = 8;
+const BRICK_VOXELS: usize = BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+
+#[derive(Debug, Clone)]
+enum VoxelStorage {
+    Dense(Vec<f32>),
+    Sparse(Vec<Option<Box<[f32; BRICK_VOXELS]>>>),
+}
+
+// voxel grid over an axis aligned box, values sit at voxel centers
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    pub resolution: [usize; 3],
+    pub min: Vec3,
+    pub max: Vec3,
+    pub max_value: f64,
+    storage: VoxelStorage,
+    majorants: Vec<f64>,
+}
+
+impl DensityGrid {
+    pub fn new(resolution: [usize; 3], min: Vec3, max: Vec3, values: Vec<f32>) -> Self {
+        Self::build(resolution, min, max, VoxelStorage::Dense(values))
+    }
+
+    // bricks are given by their brick coordinate, anything not listed is empty
+    pub fn from_bricks(resolution: [usize; 3], min: Vec3, max: Vec3, bricks: Vec<([usize; 3], Vec<f32>)>) -> Self {
+        let counts = resolution.map(|r| r.div_ceil(BRICK_SIZE));
+        let mut table: Vec<Option<Box<[f32; BRICK_VOXELS]>>> = vec![None; counts[0] * counts[1] * counts[2]];
+        for (coord, values) in bricks {
+            let mut brick = Box::new([0.0; BRICK_VOXELS]);
+            brick.copy_from_slice(&values[..BRICK_VOXELS]);
+            table[(coord[2] * counts[1] + coord[1]) * counts[0] + coord[0]] = Some(brick);
+        }
+        Self::build(resolution, min, max, VoxelStorage::Sparse(table))
+    }
+
+    fn build(resolution: [usize; 3], min: Vec3, max: Vec3, storage: VoxelStorage) -> Self {
+        let mut grid = Self {
+            resolution,
+            min,
+            max,
+            max_value: 0.0,
+            storage,
+            majorants: Vec::new(),
+        };
+        grid.build_majorants();
+        grid
+    }
+
+    // krust voxel grid files, little endian
+    //   magic "KVG1", u32 kind (0 dense, 1 sparse), u32 x3 resolution, f32 x3 min, f32 x3 max
+    //   dense:  one f32 per voxel, x fastest then y then z
+    //   sparse: u32 brick count, then per brick u32 x3 brick coordinate and 8^3 f32 values
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let mut reader = Reader { bytes: fs::read(path)?, offset: 0 };
+        if reader.take(4)? != b"KVG1" {
+            return Err(Error::new(ErrorKind::InvalidData, "not a krust voxel grid"))
+        }
+        let kind = reader.u32()?;
+        let mut resolution = [0; 3];
+        for r in resolution.iter_mut() {
+            *r = reader.u32()? as usize;
+        }
+        if resolution.contains(&0) {
+            return Err(Error::new(ErrorKind::InvalidData, "empty voxel grid"))
+        }
+        let mut corners = [0.0; 6];
+        for c in corners.iter_mut() {
+            *c = reader.f32()? as f64;
+        }
+        let min = Vec3::new(corners[0], corners[1], corners[2]);
+        let max = Vec3::new(corners[3], corners[4], corners[5]);
+
+        match kind {
+            0 => {
+                let count = resolution[0] * resolution[1] * resolution[2];
+                let values = (0..count).map(|_| reader.f32()).collect::<Result<Vec<f32>, Error>>()?;
+                Ok(Self::new(resolution, min, max, values))
+            },
+            1 => {
+                let counts = resolution.map(|r| r.div_ceil(BRICK_SIZE));
+                let brick_count = reader.u32()?;
+                let mut bricks = Vec::with_capacity(brick_count as usize);
+                for _ in 0..brick_count {
+                    let coord = [reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize];
+                    if (0..3).any(|axis| coord[axis] >= counts[axis]) {
+                        return Err(Error::new(ErrorKind::InvalidData, "brick outside the voxel grid"))
+                    }
+                    let values = (0..BRICK_VOXELS).map(|_| reader.f32()).collect::<Result<Vec<f32>, Error>>()?;
+                    bricks.push((coord, values));
+                }
+                Ok(Self::from_bricks(resolution, min, max, bricks))
+            },
+            _ => Err(Error::new(ErrorKind::InvalidData, "unknown voxel grid kind")),
+        }
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        match &self.storage {
+            VoxelStorage::Dense(values) => values[(z * self.resolution[1] + y) * self.resolution[0] + x] as f64,
+            VoxelStorage::Sparse(bricks) => {
+                let counts = self.resolution.map(|r| r.div_ceil(BRICK_SIZE));
+                let brick = ((z / BRICK_SIZE) * counts[1] + y / BRICK_SIZE) * counts[0] + x / BRICK_SIZE;
+                match &bricks[brick] {
+                    Some(values) => {
+                        let (bx, by, bz) = (x % BRICK_SIZE, y % BRICK_SIZE, z % BRICK_SIZE);
+                        values[(bz * BRICK_SIZE + by) * BRICK_SIZE + bx] as f64
+                    },
+                    None => 0.0,
+                }
+            },
+        }
+    }
+
+    // every stored voxel, empty bricks are skipped
+    fn for_each_voxel(&self, mut f: impl FnMut(usize, usize, usize, f64)) {
+        match &self.storage {
+            VoxelStorage::Dense(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    let x = i % self.resolution[0];
+                    let y = (i / self.resolution[0]) % self.resolution[1];
+                    let z = i / (self.resolution[0] * self.resolution[1]);
+                    f(x, y, z, *v as f64);
+                }
+            },
+            VoxelStorage::Sparse(bricks) => {
+                let counts = self.resolution.map(|r| r.div_ceil(BRICK_SIZE));
+                for (b, brick) in bricks.iter().enumerate() {
+                    let Some(values) = brick else { continue };
+                    let origin = [b % counts[0], (b / counts[0]) % counts[1], b / (counts[0] * counts[1])].map(|c| c * BRICK_SIZE);
+                    for (i, v) in values.iter().enumerate() {
+                        let (x, y, z) = (origin[0] + i % BRICK_SIZE, origin[1] + (i / BRICK_SIZE) % BRICK_SIZE, origin[2] + i / (BRICK_SIZE * BRICK_SIZE));
+                        if x < self.resolution[0] && y < self.resolution[1] && z < self.resolution[2] {
+                            f(x, y, z, *v as f64);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    // coarse per-cell maxima, dilated by a voxel so trilinear lookups stay bounded
+    fn build_majorants(&mut self) {
+        let counts = self.resolution.map(|r| r.div_ceil(BRICK_SIZE));
+        let mut majorants = vec![0.0f64; counts[0] * counts[1] * counts[2]];
+        let mut max_value = 0.0f64;
+        self.for_each_voxel(|x, y, z, v| {
+            max_value = max_value.max(v);
+            let range = |c: usize, axis: usize| {
+                (c.saturating_sub(1) / BRICK_SIZE)..=((c + 1) / BRICK_SIZE).min(counts[axis] - 1)
+            };
+            for cz in range(z, 2) {
+                for cy in range(y, 1) {
+                    for cx in range(x, 0) {
+                        let cell = &mut majorants[(cz * counts[1] + cy) * counts[0] + cx];
+                        *cell = cell.max(v);
+                    }
+                }
+            }
+        });
+        self.majorants = majorants;
+        self.max_value = max_value;
+    }
+
+    // trilinear lookup, zero outside the box
+    pub fn lookup(&self, p: Vec3) -> f64 {
+        let size = self.max - self.min;
+        let local = [
+            (p.x - self.min.x) / size.x,
+            (p.y - self.min.y) / size.y,
+            (p.z - self.min.z) / size.z,
+        ];
+        if local.iter().any(|l| !(0.0..=1.0).contains(l)) {
+            return 0.0
+        }
+
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let res = self.resolution[axis];
+            let x = (local[axis] * res as f64 - 0.5).clamp(0.0, (res - 1) as f64);
+            base[axis] = (x as usize).min(res.saturating_sub(2));
+            frac[axis] = if res > 1 {x - base[axis] as f64} else {0.0};
+        }
+        let next = |axis: usize| (base[axis] + 1).min(self.resolution[axis] - 1);
+
+        let mut value = 0.0;
+        for (dz, wz) in [(base[2], 1.0 - frac[2]), (next(2), frac[2])] {
+            for (dy, wy) in [(base[1], 1.0 - frac[1]), (next(1), frac[1])] {
+                for (dx, wx) in [(base[0], 1.0 - frac[0]), (next(0), frac[0])] {
+                    value += self.voxel(dx, dy, dz) * wx * wy * wz;
+                }
+            }
+        }
+        value
+    }
+
+    // walks the majorant cells along a unit direction, returning (start, end, max value) spans
+    pub fn majorant_segments(&self, origin: Vec3, direction: Vec3, near: f64, far: f64) -> Vec<(f64, f64, f64)> {
+        let counts = self.resolution.map(|r| r.div_ceil(BRICK_SIZE));
+        let size = self.max - self.min;
+        let origin = [origin.x, origin.y, origin.z];
+        let direction = [direction.x, direction.y, direction.z];
+        let min = [self.min.x, self.min.y, self.min.z];
+        let cell_size = [
+            size.x * BRICK_SIZE as f64 / self.resolution[0] as f64,
+            size.y * BRICK_SIZE as f64 / self.resolution[1] as f64,
+            size.z * BRICK_SIZE as f64 / self.resolution[2] as f64,
+        ];
+
+        // amanatides and woo traversal starting from the entry point
+        let mut cell = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            let p = origin[axis] + direction[axis] * near;
+            let c = ((p - min[axis]) / cell_size[axis]).floor() as i64;
+            cell[axis] = c.clamp(0, counts[axis] as i64 - 1);
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                let boundary = min[axis] + (cell[axis] + 1) as f64 * cell_size[axis];
+                t_next[axis] = (boundary - origin[axis]) / direction[axis];
+                t_delta[axis] = cell_size[axis] / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                let boundary = min[axis] + cell[axis] as f64 * cell_size[axis];
+                t_next[axis] = (boundary - origin[axis]) / direction[axis];
+                t_delta[axis] = -cell_size[axis] / direction[axis];
+            }
+        }
+
+        let mut segments = Vec::new();
+        let mut t = near;
+        while t < far {
+            let index = ((cell[2] as usize) * counts[1] + cell[1] as usize) * counts[0] + cell[0] as usize;
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {0} else if t_next[1] < t_next[2] {1} else {2};
+            let end = t_next[axis].min(far);
+            if end > t {
+                segments.push((t, end, self.majorants[index]));
+            }
+            t = end;
+            cell[axis] += step[axis];
+            t_next[axis] += t_delta[axis];
+            if cell[axis] < 0 || cell[axis] >= counts[axis] as i64 {
+                break
+            }
+        }
+        segments
+    }
+}
+
+struct Reader {
+    bytes: Vec<u8>,
+    offset: usize,
+}
+
+impl Reader {
+    fn take(&mut self, count: usize) -> Result<&[u8], Error> {
+        let slice = self.bytes.get(self.offset..self.offset + count)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "truncated voxel grid"))?;
+        self.offset += count;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
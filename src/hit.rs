@@ -1,5 +1,6 @@
 use crate::vec3::Vec3;
 use crate::vec2::Vec2;
+use crate::mat3::Mat3;
 use crate::ray::Ray;
 use crate::sphere::Sphere;
 use crate::tri::Tri;
@@ -24,9 +25,20 @@ pub enum Object{
 
 impl Object{
     pub fn empty() -> Object  {
-        let mat = Arc::new(Material::Principle(Principle::default()));
+        let mat = Arc::new(Material::Principle(Box::new(Principle::default())));
         Object::Sphere(Sphere::new(Vec3::black(), Vec3::black(), 0.0, 1.0, 0.001, mat))
     }
+
+    // nearest hit on a single object, leaves belonging to other objects are skipped during traversal
+    pub fn hit_filtered(&self, ray: &Ray, t_min: f64, t_max: f64, object_id: usize) -> (bool, Option<HitRecord>) {
+        match self {
+            Object::Sphere(sphere) if sphere.object_id == object_id => sphere.hit(ray, t_min, t_max),
+            Object::Tri(tri) if tri.object_id == object_id => tri.hit(ray, t_min, t_max),
+            Object::Bvh(bvh) => bvh.hit_filtered(ray, t_min, t_max, object_id),
+            Object::HittableList(hl) => hl.hit_filtered(ray, t_min, t_max, object_id),
+            _ => (false, None),
+        }
+    }
 }
 
 pub trait Hittable {
@@ -83,10 +95,19 @@ impl BoundingBox for Object {
 pub struct HitRecord {
     pub t: f64,
     pub point: Vec3,
+    pub object_point: Vec3,
+    pub object_to_world: Mat3,
     pub normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub dndu: Vec3,
+    pub dndv: Vec3,
     pub uv: Vec2,
     pub front_face: bool,
     pub material: Arc<Material>,
+    pub object_id: usize,
 }
 
 impl HitRecord {
@@ -101,6 +122,17 @@ impl HitRecord {
         }
         hit_record
     }
+
+    // every hit along the segment on a single object, probe rays use it to see through the rest of the scene
+    pub fn hit_object(world: &Object, r: &Ray, t_min: f64, t_max: f64, object_id: usize) -> Vec<HitRecord> {
+        let mut hits = Vec::new();
+        let mut t_start = t_min;
+        while let (true, Some(hit)) = world.hit_filtered(r, t_start, t_max, object_id) {
+            t_start = hit.t + 1e-6;
+            hits.push(hit);
+        }
+        hits
+    }
 }
 #[derive(Clone)]
 pub struct HittableList {
@@ -125,6 +157,18 @@ impl HittableList {
         hit_record
     }
 
+    pub fn hit_filtered(&self, r: &Ray, t_min: f64, t_max: f64, object_id: usize) -> (bool, Option<HitRecord>) {
+        let mut t_nearest = t_max;
+        let mut hit_record = (false, None);
+        for obj in &self.objects {
+            if let (true, Some(hit)) = obj.hit_filtered(r, t_min, t_nearest, object_id) {
+                t_nearest = hit.t;
+                hit_record = (true, Some(hit));
+            }
+        }
+        hit_record
+    }
+
     pub fn bounding_box(&self, time0: f64, time1: f64) ->Aabb {  
         let mut output_box: Aabb = Aabb::new(Vec3::black()*0.0001, Vec3::black()*0.001);
         for object in &self.objects {
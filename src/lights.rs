@@ -3,7 +3,6 @@ use crate::vec3::Vec3;
 use crate::vec2::Vec2;
 use crate::color::Color;
 use crate::ray::Ray;
-use crate::utility::{random_float, INF};
 use std::f64::consts::PI;
 use cgmath::{Deg, Angle};
 use nalgebra::{Rotation3, Vector3};
@@ -33,7 +32,7 @@ pub struct QuadLight {
 impl QuadLight {
     pub fn new(color: Color, intensity: f64, vertices: Vec<Vec3>) -> Self {
         let x_axis = (vertices[1] - vertices[0]).normalize();
-        let y_axis = x_axis.cross(&(vertices[3] - vertices[0])).normalize();
+        let y_axis = (vertices[3] - vertices[0]).normalize();
         let width = (vertices[1] - vertices[0]).length();
         let height = (vertices[3] - vertices[0]).length();
 
@@ -45,7 +44,7 @@ impl QuadLight {
         let uvs = vec![Vec2::zero(); 3];
         let tri1 = Tri::new(v1, normals.clone(), uvs.clone(), material.clone(), false);
         let tri2 = Tri::new(v2, normals, uvs, material.clone(), false);
-        let area = tri1.area + tri2.area;
+        let area = (vertices[1] - vertices[0]).cross(&(vertices[3] - vertices[0])).length();
         let normal = Vec3::cross(&(vertices[1]-vertices[0]), &(vertices[2]-vertices[1]));
         let tris = vec![Object::Tri(tri1), Object::Tri(tri2)];
 
@@ -115,29 +114,23 @@ impl DirectionalLight {
         Self { direction, color, intensity, softness }
     }
 
-    pub fn irradiance(&self, normal: Vec3, view_dir: Vec3, roughness: f64, lobe: &str) -> Color {
-        let cos_theta = (-self.direction).dot(&normal).max(0.0);
-        let diffuse = self.color * self.intensity * cos_theta;
-
-        let halfway = (-self.direction + view_dir).normalize();
-        let cos_alpha = normal.dot(&halfway).max(0.0);
-        let specular = self.color * self.intensity * f64::powf(cos_alpha, roughness);
-        
-        if lobe == "diffuse" { return diffuse }
-        else if lobe == "specular" { return specular }
-        else { return Color::black() }
-        
+    // response to light arriving along to_light, translucent surfaces are lit from behind
+    pub fn irradiance(&self, to_light: Vec3, normal: Vec3, view_dir: Vec3, roughness: f64, lobe: &str) -> Color {
+        let radiance = self.color * self.intensity;
+        match lobe {
+            "diffuse" => radiance * to_light.dot(&normal).max(0.0),
+            "translucent" => radiance * (-to_light).dot(&normal).max(0.0),
+            "specular" => {
+                let halfway = (to_light + view_dir).normalize();
+                radiance * normal.dot(&halfway).max(0.0).powf(roughness)
+            },
+            _ => Color::black(),
+        }
     }
 
-    pub fn shadow(&self, hit_point: &Vec3, world: &Object) -> bool {
-        let shadow_direction = -self.direction;
-        let shadow_origin = *hit_point + shadow_direction * 0.001;
+    // direction towards the light, jittered by softness, and the radiance it carries
+    pub fn sample(&self) -> (Vec3, Color) {
         let soft = Vec3::random_unit_vector() * self.softness / 10.0;
-        let ray = Ray::new(shadow_origin, shadow_direction + soft, 0.0);
-        if let (true, Some(hit_rec)) = world.hit(&ray, 0.001, INF) {
-            return true;
-
-        }
-        false
+        ((-self.direction + soft).normalize(), self.color * self.intensity)
     }
 }
\ No newline at end of file
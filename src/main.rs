@@ -14,18 +14,27 @@ mod color;
 mod buffers;
 mod render;
 mod texture;
+mod texture_cache;
 mod lights;
 mod onb;
 mod pdf;
 mod mat3;
+mod denoise;
+mod subsurface;
+mod grid;
+mod medium;
+mod noise;
+mod shader;
 use crate::render_setup::render_scene;
 
 
 #[show_image::main]
 fn main() {
-    render_scene(
+    if let Err(e) = render_scene(
         Some("examples/spheres.json"),
         "C:/krust_output/"
-    );
+    ) {
+        eprintln!("Unable to load scene: {e}");
+    }
  }
 
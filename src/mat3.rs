@@ -17,11 +17,34 @@ impl Mul<Vec3> for Mat3 {
     }
 }
 
+impl Mul<Mat3> for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, other: Mat3) -> Self::Output {
+        let mut res = Mat3::new([[0.0; 3]; 3]);
+        for i in 0..3 {
+            for j in 0..3 {
+                res.m[i][j] = (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        res
+    }
+}
+
 impl Mat3 {
     pub fn new(m: [[f64; 3]; 3]) -> Self {
         Mat3 { m }
     }
 
+    pub fn identity() -> Self {
+        Mat3::new([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    // columns are the images of the x, y and z axes
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3) -> Self {
+        Mat3::new([[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]])
+    }
+
     pub fn transpose(&self) -> Self {
         let mut res = Mat3::new([[0.0; 3]; 3]);
         for i in 0..3 {
@@ -31,4 +54,25 @@ impl Mat3 {
         }
         res
     }
-}
\ No newline at end of file
+
+    // none for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.m;
+        let cofactor = |i: usize, j: usize| {
+            let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+        if det.abs() < 1e-20 || !det.is_finite() {
+            return None
+        }
+        let mut res = Mat3::new([[0.0; 3]; 3]);
+        for i in 0..3 {
+            for j in 0..3 {
+                res.m[i][j] = cofactor(j, i) / det;
+            }
+        }
+        Some(res)
+    }
+}
@@ -1,44 +1,175 @@
 use crate::ray::Ray;
-use crate::utility::{random_float, random_int, clamp, INF};
+use crate::utility::{random_float, clamp, INF};
 use crate::vec3::Vec3;
 use crate::color::Color;
-use crate::texture::TextureMap;
+use crate::shader::{Shader, ShadingPoint};
 use std::f64::consts::PI;
-use crate::hit::{HitRecord, HittableList, Object, Hittable};
-use std::sync::Arc;
+use crate::hit::{HitRecord, Object};
+use std::sync::{Arc, OnceLock};
 use crate::onb::Onb;
-use crate::pdf::{Pdf, CosinePdf, LightPdf};
-use crate::lights::QuadLight;
-use crate::vec2::Vec2;
+use crate::pdf::{Pdf, CosinePdf, LightPdf, power_heuristic};
+use crate::medium::Medium;
+use crate::subsurface::{random_walk, diffusion_probe, SubsurfaceMode};
 
 
 pub trait Scatterable {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, lights: &Arc<Vec<Object>>) -> Option<(Ray, Color, Color, String)>;
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, lights: &Arc<Vec<Object>>, world: &Object) -> Option<(Ray, Color, Color, String)>;
 }
 
 pub trait Emits {
-    fn emit(&self) -> Color;
+    fn emit(&self, sp: &ShadingPoint) -> Color;
 }
 
 #[derive(Debug, Clone)]
 pub enum Material {
-    Principle(Principle),
+    Principle(Box<Principle>),
     Light(Light),
+    Volume(Arc<Medium>),
+    Mix(Mix),
+    Layer(Layer),
 }
 
 impl Scatterable for Material {
-    fn scatter(&self, ray: &Ray, hit_rec: &HitRecord, lights: &Arc<Vec<Object>>) -> Option<(Ray, Color, Color, String)> {
+    fn scatter(&self, ray: &Ray, hit_rec: &HitRecord, lights: &Arc<Vec<Object>>, world: &Object) -> Option<(Ray, Color, Color, String)> {
         match self {
-            Material::Principle(principle) => principle.scatter(ray, hit_rec, lights),
-            Material::Light(light) => light.scatter(ray, hit_rec, lights),
+            Material::Principle(principle) => principle.scatter(ray, hit_rec, lights, world),
+            Material::Light(light) => light.scatter(ray, hit_rec, lights, world),
+            // bare medium boundary, the ray carries on and only the medium changes
+            Material::Volume(_) => Some((Ray::new(hit_rec.point, ray.direction, ray.time), Color::white(), Color::black(), "volume".to_string())),
+            Material::Mix(mix) => mix.scatter(ray, hit_rec, lights, world),
+            Material::Layer(layer) => layer.scatter(ray, hit_rec, lights, world),
         }
     }
 }
+impl Material {
+    // first-hit surface color used as a denoising feature
+    pub fn albedo(&self, sp: &ShadingPoint) -> Color {
+        match self {
+            Material::Principle(principle) => principle.albedo(sp),
+            Material::Light(light) => light.color,
+            Material::Volume(_) => Color::black(),
+            Material::Mix(mix) => {
+                let mask = mix.mask_at(sp);
+                mix.a.albedo(sp) * (1.0 - mask) + mix.b.albedo(sp) * mask
+            },
+            Material::Layer(layer) => layer.base.albedo(sp),
+        }
+    }
+
+    pub fn opacity(&self, sp: &ShadingPoint) -> f64 {
+        match self {
+            Material::Principle(principle) => principle.opacity_texture
+                .as_ref()
+                .map(|t| t.evaluate(sp).r)
+                .unwrap_or(principle.opacity),
+            Material::Mix(mix) => {
+                let mask = mix.mask_at(sp);
+                mix.a.opacity(sp) * (1.0 - mask) + mix.b.opacity(sp) * mask
+            },
+            Material::Layer(layer) => layer.base.opacity(sp),
+            _ => 1.0,
+        }
+    }
+
+    // no opacity below one anywhere, so hits can skip building a shading point for the cutout
+    pub fn opaque(&self) -> bool {
+        match self {
+            Material::Principle(principle) => principle.opacity >= 1.0 && principle.opacity_texture.is_none(),
+            Material::Mix(mix) => mix.a.opaque() && mix.b.opaque(),
+            Material::Layer(layer) => layer.base.opaque(),
+            _ => true,
+        }
+    }
+
+    // stochastic cutout, a hit is ignored with probability 1 - opacity so every ray type sees through it
+    pub fn cutout(&self, sp: &ShadingPoint) -> bool {
+        let opacity = self.opacity(sp);
+        opacity < 1.0 && random_float() >= opacity
+    }
+
+    // resolves mixes down to the material that shades this hit, layers stay whole
+    pub fn select(&self, sp: &ShadingPoint) -> &Material {
+        match self {
+            Material::Mix(mix) => mix.pick(sp).select(sp),
+            _ => self,
+        }
+    }
+
+    // the principle whose weights drive directional light shading, a layer shades as its base
+    pub fn surface(&self) -> Option<&Principle> {
+        match self {
+            Material::Principle(principle) => Some(principle),
+            Material::Layer(layer) => layer.base.surface(),
+            _ => None,
+        }
+    }
+
+    // straight through transmittance for shadow rays, medium boundaries pass everything
+    pub fn shadow_transmittance(&self, sp: &ShadingPoint, rec: &HitRecord, ray: &Ray) -> Color {
+        match self {
+            Material::Principle(principle) => principle.shadow_transmittance(sp, rec, ray),
+            Material::Volume(_) => Color::white(),
+            Material::Light(_) => Color::black(),
+            Material::Mix(mix) => mix.pick(sp).shadow_transmittance(sp, rec, ray),
+            Material::Layer(layer) => {
+                let weight = if rec.front_face {layer.weight_at(sp)} else {0.0};
+                let coat = layer.coat.shadow_transmittance(sp, rec, ray);
+                let covered = coat * weight + Color::white() * (1.0 - weight);
+                let transmittance = covered * layer.base.shadow_transmittance(sp, rec, ray);
+                Color::new(transmittance.r, transmittance.g, transmittance.b, 1.0)
+            },
+        }
+    }
+
+    // transmittance towards the viewer through the front face, what a coat lets through of the base below
+    pub fn front_transmittance(&self, sp: &ShadingPoint) -> Color {
+        let transmittance = match self {
+            Material::Principle(principle) => principle.transmittance(sp, sp.view.dot(&sp.normal).abs(), true, 0.0),
+            Material::Volume(_) => Color::white(),
+            Material::Light(_) => Color::black(),
+            Material::Mix(mix) => {
+                let mask = mix.mask_at(sp);
+                mix.a.front_transmittance(sp) * (1.0 - mask) + mix.b.front_transmittance(sp) * mask
+            },
+            Material::Layer(layer) => {
+                let weight = layer.weight_at(sp);
+                let covered = layer.coat.front_transmittance(sp) * weight + Color::white() * (1.0 - weight);
+                covered * layer.base.front_transmittance(sp)
+            },
+        };
+        Color::new(transmittance.r, transmittance.g, transmittance.b, 1.0)
+    }
+
+    // medium enclosed by a closed mesh using this material
+    pub fn interior_medium(&self) -> Option<Arc<Medium>> {
+        match self {
+            Material::Principle(principle) if principle.thin_walled => None,
+            Material::Principle(principle) => principle.medium.clone(),
+            Material::Volume(medium) => Some(medium.clone()),
+            Material::Light(_) => None,
+            Material::Mix(mix) => mix.a.interior_medium().or_else(|| mix.b.interior_medium()),
+            Material::Layer(layer) => layer.base.interior_medium(),
+        }
+    }
+}
+
 impl Emits for Material {
-    fn emit(&self) -> Color {
+    fn emit(&self, sp: &ShadingPoint) -> Color {
         match self {
-            Material::Principle(principle) => principle.emit(),
-            Material::Light(light) => light.emit(),
+            Material::Principle(principle) => principle.emit(sp),
+            Material::Light(light) => light.emit(sp),
+            Material::Volume(_) => Color::black(),
+            Material::Mix(mix) => {
+                let mask = mix.mask_at(sp);
+                mix.a.emit(sp) * (1.0 - mask) + mix.b.emit(sp) * mask
+            },
+            // the coat adds its own emission and dims the base's by what it lets through, as in scatter
+            Material::Layer(layer) => {
+                let weight = layer.weight_at(sp);
+                let covered = layer.coat.front_transmittance(sp) * weight + Color::white() * (1.0 - weight);
+                let emission = layer.coat.emit(sp) * weight + covered * layer.base.emit(sp);
+                Color::new(emission.r, emission.g, emission.b, 1.0)
+            },
         }
     }
 }
@@ -47,6 +178,10 @@ impl Emits for Material {
 pub struct Principle {
     pub diffuse: Color,
     pub diffuse_weight: f64,
+    pub diffuse_roughness: f64,
+    pub opacity: f64,
+    pub thin_walled: bool,
+    pub diffuse_transmission: f64,
     pub specular: Color,
     pub specular_weight: f64,
     pub roughness: f64,
@@ -54,77 +189,64 @@ pub struct Principle {
     pub metallic: f64,
     pub refraction: f64,
     pub emission: Color,
-    pub bump: f64,
     pub bump_strength: f64,
     pub normal_strength: f64,
-    pub diffuse_texture: Option<TextureMap>,
-    pub diffuse_weight_texture: Option<TextureMap>,
-    pub specular_texture: Option<TextureMap>,
-    pub specular_weight_texture: Option<TextureMap>,
-    pub roughness_texture: Option<TextureMap>,
-    pub metallic_texture: Option<TextureMap>,
-    pub refraction_texture: Option<TextureMap>,
-    pub emission_texture: Option<TextureMap>,
-    pub bump_texture: Option<TextureMap>,
-    pub normal_texture: Option<TextureMap>,
+    pub transmission: Color,
+    pub transmission_depth: f64,
+    pub anisotropy: f64,
+    pub anisotropy_rotation: f64,
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    pub clearcoat_ior: f64,
+    pub sheen: f64,
+    pub sheen_color: Color,
+    pub sheen_roughness: f64,
+    pub subsurface: f64,
+    pub subsurface_color: Color,
+    pub subsurface_radius: Color,
+    pub subsurface_anisotropy: f64,
+    pub subsurface_mode: SubsurfaceMode,
+    pub thin_film_thickness: f64,
+    pub thin_film_ior: f64,
+    pub metal_edge_tint: Color,
+    pub metal_ior: Option<(Color, Color)>,
+    pub medium: Option<Arc<Medium>>,
+    pub diffuse_texture: Option<Shader>,
+    pub diffuse_weight_texture: Option<Shader>,
+    pub diffuse_roughness_texture: Option<Shader>,
+    pub opacity_texture: Option<Shader>,
+    pub diffuse_transmission_texture: Option<Shader>,
+    pub specular_texture: Option<Shader>,
+    pub specular_weight_texture: Option<Shader>,
+    pub roughness_texture: Option<Shader>,
+    pub metallic_texture: Option<Shader>,
+    pub refraction_texture: Option<Shader>,
+    pub emission_texture: Option<Shader>,
+    pub bump_texture: Option<Shader>,
+    pub normal_texture: Option<Shader>,
+    pub anisotropy_texture: Option<Shader>,
+    pub anisotropy_rotation_texture: Option<Shader>,
+    pub clearcoat_texture: Option<Shader>,
+    pub clearcoat_roughness_texture: Option<Shader>,
+    pub clearcoat_normal_texture: Option<Shader>,
+    pub sheen_texture: Option<Shader>,
+    pub sheen_color_texture: Option<Shader>,
+    pub sheen_roughness_texture: Option<Shader>,
+    pub subsurface_texture: Option<Shader>,
+    pub subsurface_color_texture: Option<Shader>,
+    pub thin_film_thickness_texture: Option<Shader>,
+    pub metal_edge_tint_texture: Option<Shader>,
 }
 
 impl Principle {
-    pub fn new(
-        diffuse: Color,
-        diffuse_weight: f64,
-        specular: Color,
-        specular_weight: f64,
-        roughness: f64,
-        ior: f64,
-        metallic: f64,
-        refraction: f64,
-        emission: Color,
-        bump: f64,
-        bump_strength: f64,
-        normal_strength: f64,
-        diffuse_texture: Option<TextureMap>,
-        diffuse_weight_texture: Option<TextureMap>,
-        specular_texture: Option<TextureMap>,
-        specular_weight_texture: Option<TextureMap>,
-        roughness_texture: Option<TextureMap>,
-        metallic_texture: Option<TextureMap>,
-        refraction_texture: Option<TextureMap>,
-        emission_texture: Option<TextureMap>,
-        bump_texture: Option<TextureMap>,
-        normal_texture: Option<TextureMap>,
-        
-    ) -> Principle {
-        Principle {
-            diffuse,
-            diffuse_weight,
-            specular,
-            specular_weight,
-            roughness,
-            ior,
-            metallic,
-            refraction,
-            emission,
-            bump,
-            bump_strength,
-            normal_strength,
-            diffuse_texture,
-            diffuse_weight_texture,
-            specular_texture,
-            specular_weight_texture,
-            roughness_texture,
-            metallic_texture,
-            refraction_texture,
-            emission_texture,
-            bump_texture,
-            normal_texture,
-        }
-    }
-
     pub fn default() -> Self {
         Self {
             diffuse: Color::black(),
             diffuse_weight: 1.0,
+            diffuse_roughness: 0.0,
+            opacity: 1.0,
+            thin_walled: false,
+            diffuse_transmission: 0.0,
             specular: Color::white(),
             specular_weight: 1.0,
             roughness: 0.5,
@@ -132,11 +254,33 @@ impl Principle {
             metallic: 0.0,
             refraction: 0.0,
             emission: Color::black(),
-            bump: 0.0,
             bump_strength: 0.0,
             normal_strength: 0.0,
+            transmission: Color::white(),
+            transmission_depth: 0.0,
+            anisotropy: 0.0,
+            anisotropy_rotation: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.1,
+            clearcoat_ior: 1.5,
+            sheen: 0.0,
+            sheen_color: Color::white(),
+            sheen_roughness: 0.3,
+            subsurface: 0.0,
+            subsurface_color: Color::white(),
+            subsurface_radius: Color::new(1.0, 0.2, 0.1, 1.0),
+            subsurface_anisotropy: 0.0,
+            subsurface_mode: SubsurfaceMode::RandomWalk,
+            thin_film_thickness: 0.0,
+            thin_film_ior: 1.33,
+            metal_edge_tint: Color::white(),
+            metal_ior: None,
+            medium: None,
             diffuse_texture: None,
             diffuse_weight_texture: None,
+            diffuse_roughness_texture: None,
+            opacity_texture: None,
+            diffuse_transmission_texture: None,
             specular_texture: None,
             specular_weight_texture: None,
             roughness_texture: None,
@@ -144,216 +288,389 @@ impl Principle {
             refraction_texture: None,
             emission_texture: None,
             bump_texture: None,
-            normal_texture: None
+            normal_texture: None,
+            anisotropy_texture: None,
+            anisotropy_rotation_texture: None,
+            clearcoat_texture: None,
+            clearcoat_roughness_texture: None,
+            clearcoat_normal_texture: None,
+            sheen_texture: None,
+            sheen_color_texture: None,
+            sheen_roughness_texture: None,
+            subsurface_texture: None,
+            subsurface_color_texture: None,
+            thin_film_thickness_texture: None,
+            metal_edge_tint_texture: None,
         }
     }
     
-    pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * f64::powi(1.0 - cosine, 5)
+
+    // beer-lambert attenuation over a distance travelled inside the medium
+    pub fn absorption(&self, distance: f64) -> Color {
+        if self.transmission_depth <= 0.0 {
+            return Color::white()
+        }
+        let sigma = |c: f64| -f64::max(c, 0.0001).ln() / self.transmission_depth;
+        Color::new(
+            (-sigma(self.transmission.r) * distance).exp(),
+            (-sigma(self.transmission.g) * distance).exp(),
+            (-sigma(self.transmission.b) * distance).exp(),
+            1.0
+        )
     }
 
-    pub fn scatter_pdf(r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+    pub fn albedo(&self, sp: &ShadingPoint) -> Color {
+        let diffuse = self.diffuse_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp))
+            .unwrap_or(self.diffuse);
+        let refraction = self.refraction_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp).r)
+            .unwrap_or(self.refraction);
+        let albedo = diffuse * (1.0 - refraction) + Color::white() * refraction;
+        Color::new(albedo.r, albedo.g, albedo.b, 1.0)
+    }
+
+    // what a shadow ray carries straight through the surface. refraction passes what fresnel
+    // doesn't reflect, diffuse transmission its tinted albedo. neither bends the ray, so light
+    // focused through glass is still left to the scattered paths
+    pub fn shadow_transmittance(&self, sp: &ShadingPoint, rec: &HitRecord, ray: &Ray) -> Color {
+        let cos_i = ray.direction.normalize().dot(&rec.normal).abs();
+        let distance = if rec.front_face {0.0} else {rec.t * ray.direction.length()};
+        self.transmittance(sp, cos_i, rec.front_face, distance)
+    }
+
+    // straight through transmittance at an incidence angle, distance is the path inside when leaving a solid
+    pub fn transmittance(&self, sp: &ShadingPoint, cos_i: f64, front_face: bool, distance: f64) -> Color {
+        let value = |texture: &Option<Shader>, value: f64| texture
+            .as_ref()
+            .map(|t| t.evaluate(sp).r)
+            .unwrap_or(value);
+        let metallic = clamp(value(&self.metallic_texture, self.metallic), 0.0, 1.0);
+        let refraction = clamp(value(&self.refraction_texture, self.refraction), 0.0, 1.0);
+        let diffuse_transmission = clamp(value(&self.diffuse_transmission_texture, self.diffuse_transmission), 0.0, 1.0);
+        if metallic >= 1.0 || (refraction <= 0.0 && diffuse_transmission <= 0.0) {
+            return Color::black()
+        }
+
+        let eta = if front_face || self.thin_walled {self.ior} else {1.0 / self.ior};
+        let mut reflectance = fresnel_dielectric(cos_i, eta);
+        if self.thin_walled {
+            reflectance = 2.0 * reflectance / (1.0 + reflectance);
+        }
+        // tinted like the refraction lobe, absorbed over the path inside when leaving a solid
+        let tint = if !front_face && !self.thin_walled {
+            self.absorption(distance)
+        } else if self.thin_walled || self.transmission_depth <= 0.0 {
+            self.transmission
+        } else {
+            Color::white()
+        };
+        let diffuse = self.diffuse_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp))
+            .unwrap_or(self.diffuse);
+        let diffuse_weight = clamp(value(&self.diffuse_weight_texture, self.diffuse_weight) - metallic - refraction, 0.0, 1.0);
+
+        let refracted = tint * (refraction * (1.0 - reflectance));
+        let translucent = diffuse * (diffuse_weight * diffuse_transmission);
+        let transmittance = (refracted + translucent) * (1.0 - metallic);
+        Color::new(transmittance.r, transmittance.g, transmittance.b, 1.0)
+    }
+
+    pub fn scatter_pdf(rec: &HitRecord, scattered: &Ray) -> f64 {
         let cosine = Vec3::dot(&rec.normal, &scattered.direction.normalize());
-        return if cosine < 0.0 {0.0} else {cosine / PI}
+        if cosine < 0.0 {0.0} else {cosine / PI}
     }
 }
 
 impl Emits for Principle {
-    fn emit(&self) -> Color {
-        self.emission
+    fn emit(&self, sp: &ShadingPoint) -> Color {
+        self.emission_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp))
+            .unwrap_or(self.emission)
     }
 }
 
 impl Scatterable for Principle {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lights: &Arc<Vec<Object>>) -> Option<(Ray, Color, Color, String)> {
-        // sample textures if available
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lights: &Arc<Vec<Object>>, world: &Object) -> Option<(Ray, Color, Color, String)> {
+        // evaluate shading networks if available
+        let sp = ShadingPoint::new(rec, r_in);
         let mut diffuse = self.diffuse;
-        if let Some(d) = &self.diffuse_texture {
-            diffuse = self.diffuse_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0));
+        if let Some(t) = &self.diffuse_texture {
+            diffuse = t.evaluate(&sp);
         } 
 
         let mut diffuse_weight = self.diffuse_weight;
-        if let Some(dwt) = &self.diffuse_weight_texture {
-            diffuse_weight = self.diffuse_weight_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
+        if let Some(t) = &self.diffuse_weight_texture {
+            diffuse_weight = t.evaluate(&sp).r;
         } 
 
         let mut specular = self.specular;
-        if let Some(st) = &self.specular_texture {
-            specular = self.specular_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0));
+        if let Some(t) = &self.specular_texture {
+            specular = t.evaluate(&sp);
         } 
 
         let mut specular_weight = self.specular_weight;
-        if let Some(swt) = &self.specular_weight_texture {
-            specular_weight = self.specular_weight_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
+        if let Some(t) = &self.specular_weight_texture {
+            specular_weight = t.evaluate(&sp).r;
         }
         
         let mut roughness = self.roughness;
-        if let Some(rt) = &self.roughness_texture {
-            roughness = self.roughness_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
+        if let Some(t) = &self.roughness_texture {
+            roughness = t.evaluate(&sp).r;
         } 
 
         let mut metallic = self.metallic;
-        if let Some(mt) = &self.metallic_texture {
-            metallic = self.metallic_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
+        if let Some(t) = &self.metallic_texture {
+            metallic = t.evaluate(&sp).r;
         } 
 
         let mut refraction = self.refraction;
-        if let Some(rft) = &self.refraction_texture {
-            refraction = self.refraction_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
-        } 
-
-        let mut emission = self.emission;
-        if let Some(et) = &self.emission_texture {
-            emission = self.emission_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0));
-        } 
-
-        let mut bump = 0.0;
-        let mut bump_gradient = Color::black();
-        let mut has_bump = false;
-        if let Some(et) = &self.bump_texture {
-            bump = self.bump_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y).r)
-                .unwrap_or_else(|| 0.0);
-            bump_gradient = self.bump_texture
-                .as_ref()
-                .map(|t| t.get_gradient(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::black());
-            has_bump = true;
-        } 
-
-        let mut has_normal = false;
-        let mut normal_map = Color::black();
-        if let Some(nm) = &self.normal_texture {
-            normal_map = self.normal_texture
-                .as_ref()
-                .map(|t| t.sample(rec.uv.x, rec.uv.y))
-                .unwrap_or_else(|| Color::black());
-                has_normal = true;
+        if let Some(t) = &self.refraction_texture {
+            refraction = t.evaluate(&sp).r;
         } 
 
-        // bump map
+        let emission = self.emit(&sp);
+
+        let diffuse_roughness = self.diffuse_roughness_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.diffuse_roughness);
+
+        let diffuse_transmission = self.diffuse_transmission_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.diffuse_transmission);
+
+        let anisotropy = self.anisotropy_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.anisotropy);
+
+        let anisotropy_rotation = self.anisotropy_rotation_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.anisotropy_rotation);
+
+        let clearcoat = self.clearcoat_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.clearcoat);
+
+        let clearcoat_roughness = self.clearcoat_roughness_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.clearcoat_roughness);
+
+        let sheen = self.sheen_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.sheen);
+
+        let sheen_color = self.sheen_color_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp))
+            .unwrap_or(self.sheen_color);
+
+        let sheen_roughness = self.sheen_roughness_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.sheen_roughness);
+
+        let subsurface = self.subsurface_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r)
+            .unwrap_or(self.subsurface);
+
+        let subsurface_color = self.subsurface_color_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp))
+            .unwrap_or(self.subsurface_color);
+
+        // a thickness texture scales the film between zero and its thickness in nm
+        let thin_film_thickness = self.thin_film_thickness_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp).r * self.thin_film_thickness)
+            .unwrap_or(self.thin_film_thickness);
+
+        let metal_edge_tint = self.metal_edge_tint_texture
+            .as_ref()
+            .map(|t| t.evaluate(&sp))
+            .unwrap_or(self.metal_edge_tint);
+
+        // bump map, the height displaces the front side along the normal by bump_strength world units
+        // per unit of texture. the displaced surface's dP/du and dP/dv give the new normal
         let mut perturbed_normal = rec.normal;
-        let (t, b) = rec.normal.tangent_bitangent();
-        if has_bump {            
-            let bump_gradient = bump_gradient * self.bump_strength * 10.0;
-            perturbed_normal = (
-                rec.normal
-                + (bump_gradient.g * t * rec.normal)
-                + (bump_gradient.r * b * rec.normal))
-                .normalize();     
+        let (t, b) = (rec.tangent, rec.bitangent);
+        if let Some(bt) = &self.bump_texture {
+            // without a usable uv layout the tangent frame stands in for the parameterization
+            let (dpdu, dpdv) = if rec.dpdu.cross(&rec.dpdv).length_squared() > 1e-24 {(rec.dpdu, rec.dpdv)} else {(t, b)};
+            let front_normal = if rec.front_face {rec.normal} else {-rec.normal};
+            let height = bt.evaluate(&sp).r * self.bump_strength;
+            let (dhdu, dhdv) = bt.height_derivatives(&sp, dpdu, dpdv);
+            let displaced_u = dpdu + front_normal * (dhdu * self.bump_strength) + rec.dndu * height;
+            let displaced_v = dpdv + front_normal * (dhdv * self.bump_strength) + rec.dndv * height;
+            let n = displaced_u.cross(&displaced_v);
+            if n.length_squared() > 1e-24 {
+                let n = n.normalize();
+                perturbed_normal = if n.dot(&rec.normal) < 0.0 {-n} else {n};
+            }
         }
 
-        //  normal map
-        if has_normal {
-            let normal_offset = normal_map.to_normal_vec(t, b, rec.normal) * self.normal_strength;
-            perturbed_normal = (perturbed_normal + normal_offset).normalize();
+        //  normal map, through the mesh's tangent frame on top of any bump
+        if let Some(nm) = &self.normal_texture {
+            perturbed_normal = nm.normal(&sp, t, b, perturbed_normal, self.normal_strength);
         }
 
         // unit direction
         let unit_direction = r_in.direction.normalize();
 
-        // light pdf
-        let mut to_light = Vec3::zeros();
-        let mut on_light = Vec3::zeros();
-        let mut sum_pdf = 0.0;
-        let mut pdf_val = 0.0;
-        for (i, light) in lights.iter().enumerate() {
-            match light {
-                Object::QuadLight(quad_light) => {
-                    let distance_squared = (quad_light.position - rec.point).length_squared();
-                    sum_pdf += quad_light.area / distance_squared;
-                }
-                _ => {}
+        // thin walls have no inside, both sides shade like the front
+        let front_face = rec.front_face || self.thin_walled;
+
+        // light sampling is skipped when there is nothing to sample
+        let light_pdf = LightPdf::new(lights.clone(), rec.point);
+        let light_prob = if lights.is_empty() {0.0} else {0.5};
+
+        // clearcoat, the base below only sees what the coat's fresnel lets through
+        if clearcoat > 0.0 && front_face {
+            let v = -unit_direction;
+            let n = match &self.clearcoat_normal_texture {
+                Some(cn) => cn.normal(&sp, t, b, rec.normal, self.normal_strength),
+                None => rec.normal,
+            };
+            let coat_prob = clearcoat * fresnel_dielectric(f64::max(n.dot(&v), 0.0), self.clearcoat_ior);
+            if random_float() < coat_prob {
+                let alpha = f64::max(clearcoat_roughness, 0.001);
+                let mut uvw = Onb::new();
+                uvw.build_from_w_tangent(n, rec.tangent);
+                let (l, ggx, ldh, multiple) = sample_ggx_lobe_ms(v, &uvw, alpha, alpha, &light_pdf, light_prob);
+                let scattered = Ray::new(rec.point, l, r_in.time);
+
+                // coat fresnel at the sampled half vector over the fresnel used to pick the coat
+                let fresnel = |cos: f64| Color::white() * fresnel_dielectric(cos, self.clearcoat_ior);
+                let f = if multiple {
+                    multiple_scatter_fresnel(average_fresnel(fresnel), ggx_average_albedo(alpha))
+                } else {
+                    fresnel(ldh)
+                };
+                let mut attenuation = f * (clearcoat * ggx / coat_prob);
+                attenuation.a = 1.0;
+
+                return Some((scattered, attenuation, emission, "clearcoat".to_string()))
             }
         }
 
-        // pick a light
-        let mut chosen_light = None;
-        for (i, light) in lights.iter().enumerate() {
-            match light {
-                Object::QuadLight(quad_light) => {
-                    let distance_squared = (quad_light.position - rec.point).length_squared();
-                    let pdf = quad_light.area / distance_squared;
-                    if chosen_light.is_none() && random_float() < pdf / sum_pdf {
-                        chosen_light = Some(quad_light);
-                        pdf_val = pdf / sum_pdf;
-                    }
-                }
-                _ => {}
+        // sheen, the base is attenuated by the sheen albedo like the standard surface layering
+        if sheen > 0.0 && front_face {
+            let v = -unit_direction;
+            let n = perturbed_normal;
+            let ndv = n.dot(&v);
+            let sheen_roughness = clamp(sheen_roughness, 0.01, 1.0);
+            let sheen_prob = sheen * sheen_albedo(ndv, sheen_roughness);
+            if ndv > 0.0 && random_float() < sheen_prob {
+                let cosine_pdf = CosinePdf::new(n);
+                let direct = random_float() < light_prob;
+                let l = if direct {light_pdf.generate()} else {cosine_pdf.generate()}.normalize();
+                let scattered = Ray::new(rec.point, l, r_in.time);
+
+                // one-sample mis between light and cosine sampling
+                let cosine_pdf_val = cosine_pdf.value(&l) * (1.0 - light_prob);
+                let light_pdf_val = light_pdf.value(&l) * light_prob;
+                let (chosen_pdf, other_pdf) = if direct {(light_pdf_val, cosine_pdf_val)} else {(cosine_pdf_val, light_pdf_val)};
+                let weight = if chosen_pdf > 0.0 {power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+                let ndl = n.dot(&l);
+                let brdf = if ndl > 0.0 {sheen_brdf(n, v, l, sheen_roughness)} else {0.0};
+                let mut attenuation = sheen_color * (sheen * brdf * ndl * weight / sheen_prob);
+                attenuation.a = 1.0;
+
+                return Some((scattered, attenuation, emission, "sheen".to_string()))
             }
         }
 
-        // generate ray direction based on chosen light
-        if let Some(quad_light) = chosen_light {
-            // pick a random point on the chosen light
-            let (s, t) = (random_float(), random_float());
-            on_light = quad_light.position
-                + quad_light.x_axis * (s - 0.5) * quad_light.width
-                + quad_light.y_axis * (t - 0.5) * quad_light.height;  
-
-            // compute the direction
-            to_light = on_light - rec.point;
-        }
-        
         // compute probability of each lobe
         let roll = random_float();
         diffuse_weight = clamp(diffuse_weight - metallic - refraction, 0.0, 1.0);
         let metal = metallic > roll;
-        let refract = refraction > roll * 2.0;
-        let mut specular_prob = specular_weight / (specular_weight + diffuse_weight);
-
-        // refraction
-        if refract {               
-            let cos_theta = f64::min(Vec3::dot(&(unit_direction * -1.0), &perturbed_normal), 1.0);
-            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-            let refraction_ratio = if cos_theta > 0.0 {
-                if rec.front_face {
-                    1.0 / self.ior
-                } else {
-                    self.ior
-                }
+        let refract = refraction > random_float();
+        let specular_prob = specular_weight / (specular_weight + diffuse_weight);
+
+        // dielectric, fresnel picks between reflection and transmission
+        if refract {
+            let alpha = f64::max(roughness, 0.001);
+            let n = perturbed_normal;
+            let wi = -unit_direction;
+            let eta = if front_face {self.ior} else {1.0 / self.ior};
+
+            // sample a visible microfacet normal, smooth glass collapses to the shading normal
+            let mut uvw = Onb::new();
+            uvw.build_from_w(n);
+            let m = if roughness > 0.0 {
+                uvw.local(ggx_sample_vndf(uvw.to_local(wi), alpha, alpha, random_float(), random_float()))
             } else {
-                self.ior
+                n
             };
+            let idm = wi.dot(&m);
 
-            let mut direction = unit_direction + Vec3::random_unit_vector() * roughness; 
-            let real_refract = false;
-            if real_refract {
-                direction = Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
-                     + Vec3::random_unit_vector() * roughness;
+            // a film on the outside reflects per channel, pick by the average and reweight
+            let mut reflectance = if thin_film_thickness > 0.0 && front_face {
+                thin_film_fresnel(idm, thin_film_thickness, self.thin_film_ior, [self.ior; 3], [0.0; 3])
+            } else {
+                Color::white() * fresnel_dielectric(idm, eta)
+            };
+
+            // a thin wall reflects off both faces, the bounces between them sum to 2R / (1 + R)
+            if self.thin_walled {
+                let slab = |r: f64| 2.0 * r / (1.0 + r);
+                reflectance = Color::new(slab(reflectance.r), slab(reflectance.g), slab(reflectance.b), 1.0);
             }
+            let fresnel = reflectance.sum() / 3.0;
+            let reflect = random_float() < fresnel;
+
+            // thin walls transmit without bending, mirroring the reflection through the surface
+            let direction = if reflect {
+                Vec3::reflect(unit_direction, m)
+            } else if self.thin_walled {
+                let reflected = Vec3::reflect(unit_direction, m);
+                reflected - n * (2.0 * reflected.dot(&n))
+            } else {
+                Vec3::refract(&unit_direction, &m, 1.0 / eta)
+            }.normalize();
+
+            // walter et al. 2007 btdf under vndf sampling reduces to G2 / G1, fresnel cancels with the lobe choice
+            let ndi = wi.dot(&n);
+            let ndo = direction.dot(&n);
+            let valid = idm > 0.0 && if reflect {ndo > 0.0} else {ndo < 0.0};
+            let mut attenuation = if !valid {
+                Color::black()
+            } else if roughness > 0.0 {
+                Color::white() * (smith_g2(ndi.abs(), ndo.abs(), alpha) / smith_g1(ndi.abs(), alpha))
+            } else {
+                Color::white()
+            };
 
-            let scattered = Ray::new(rec.point, direction, r_in.time);
-            let attenuation = Color::white();
+            // colored reflectance was picked by its average, reweight per channel
+            attenuation = if reflect {
+                attenuation * reflectance / fresnel
+            } else {
+                attenuation * (Color::white() - reflectance) / (1.0 - fresnel)
+            };
+
+            // absorb along the path travelled inside, or tint at the surface without a depth
+            if !front_face {
+                let distance = rec.t * r_in.direction.length();
+                attenuation = attenuation * self.absorption(distance);
+            } else if !reflect && (self.thin_walled || self.transmission_depth <= 0.0) {
+                attenuation = attenuation * self.transmission;
+            }
+            attenuation.a = 1.0;
 
-            return Some((scattered, attenuation * 2.0, emission, "refraction".to_string()));
+            let scattered = Ray::new(rec.point, direction, r_in.time);
+            return Some((scattered, attenuation, emission, "refraction".to_string()));
         } 
         
         // specular
@@ -361,88 +678,121 @@ impl Scatterable for Principle {
             // reflectance values
             let ior_to_f0 = ((self.ior - 1.0) / (self.ior + 1.0)).powf(2.0);
             let basic_f0 = Vec3::new(ior_to_f0, ior_to_f0, ior_to_f0);
-            let metal_f0 = Vec3::new(0.85, 0.85, 0.85);
             
             // roughness, view angle, normal
-            let r = if roughness == 0.0 {0.001} else {roughness};
-            let v = -r_in.direction.normalize();
-            let mut n = perturbed_normal.normalize();
-            let mut l = n;
-            let mut h = n;
-            
-            // on axis check
-            let t = 0.02;
-            if n.x > 1.0-t && n.y < t && n.z < t
-            || n.x < t && n.y > 1.0-t && n.z < t
-            || n.x < t && n.y < t && n.z > 1.0-t 
-            {n = n + Vec3::random_unit_vector() * roughness;}
-
-            // determine direction of reflected ray
-            let direct = random_float() < 0.5;
-            if direct {
-                // sample a light                          
-                l = to_light;
-                h = (v + l).normalize();
-            } else {
-                // sample random ggx vector
-                h = ggx_sample(r, n).normalize();
-                l = (2.0 * v.dot(&h) * h - v).normalize();
-            }
+            let (alpha_x, alpha_y) = anisotropic_alpha(roughness, anisotropy);
+            let v = -unit_direction;
+            let n = perturbed_normal.normalize();
+
+            // shading frame follows the rotated surface tangent
+            let tangent = {
+                let mut frame = Onb::new();
+                frame.build_from_w_tangent(n, rec.tangent);
+                let angle = anisotropy_rotation * 2.0 * PI;
+                frame.u() * angle.cos() + frame.v() * angle.sin()
+            };
+            let mut uvw = Onb::new();
+            uvw.build_from_w_tangent(n, tangent);
 
-            // scattered ray
+            // sample either a light or a visible ggx normal
+            let (l, ggx, ldh, multiple) = sample_ggx_lobe_ms(v, &uvw, alpha_x, alpha_y, &light_pdf, light_prob);
             let scattered =  Ray::new(rec.point, l, r_in.time); 
 
-            // dots
-            let ndv = f64::max(n.dot(&v), 0.0);
-            let ndh = f64::max(n.dot(&h), 0.0);
-            let ndl = f64::max(n.dot(&l), 0.0);
-            let ldh = f64::max(l.dot(&h), 0.0);
-
-            // ggx
-            let f0 = if metal {metal_f0} else {basic_f0};
-            let d: f64 = ggx_distribution(ndh, r);
-            let g: f64 = schlick_masking(ndl, ndv, r);
-            let f: Color = schlick_fresnel(f0, ldh);
-            let ggx =  f * g * d / f64::max((4.0 * ndv * ndl), 0.015);
-
-            // compute weights
-            let light_pdf = LightPdf::new(lights.clone(), rec.point, rec.normal);
-            let direct_weight = light_pdf.value(&scattered.direction);
-            let indirect_weight = d * ndh / (4.0 * ldh);
-            let weight = direct_weight * 0.5 + indirect_weight * 0.5;                  
+            // metals use a complex ior, either given or fit to the diffuse color and edge tint
+            let (eta, k) = if metal {
+                let (eta, k) = self.metal_ior.unwrap_or_else(|| edge_tint_to_ior(diffuse, metal_edge_tint));
+                ([eta.r, eta.g, eta.b], [k.r, k.g, k.b])
+            } else {
+                ([self.ior; 3], [0.0; 3])
+            };
+
+            // a thin film replaces the base fresnel
+            let fresnel = |cos: f64| if thin_film_thickness > 0.0 {
+                thin_film_fresnel(cos, thin_film_thickness, self.thin_film_ior, eta, k)
+            } else if metal {
+                Color::new(
+                    fresnel_conductor(cos, eta[0], k[0]),
+                    fresnel_conductor(cos, eta[1], k[1]),
+                    fresnel_conductor(cos, eta[2], k[2]),
+                    1.0
+                )
+            } else {
+                schlick_fresnel(basic_f0, cos)
+            };
+            let f: Color = if multiple {
+                multiple_scatter_fresnel(average_fresnel(fresnel), ggx_average_albedo((alpha_x * alpha_y).sqrt()))
+            } else {
+                fresnel(ldh)
+            };
+            let tint = if metal {Color::white()} else {specular};
 
             // final color composite
-            let attenuation = 
-            if metal {diffuse * ggx * ndl / (weight * specular_prob)} 
-            else {specular * ggx * ndl / (weight * specular_prob)};
+            let mut attenuation = tint * f * (ggx / specular_prob);
+            attenuation.a = 1.0;
 
-            return Some((scattered, attenuation, emission, "specular".to_string()))          
+            Some((scattered, attenuation, emission, "specular".to_string()))
 
         } else {            
+            // subsurface takes over part of the diffuse lobe, walking inside the mesh or probing a diffusion profile
+            if subsurface > random_float() && rec.front_face && !self.thin_walled {
+                let entry = Ray::new(rec.point, CosinePdf::new(-rec.normal).generate(), r_in.time);
+                let walk = match self.subsurface_mode {
+                    SubsurfaceMode::RandomWalk => random_walk(&entry, world, rec.object_id, subsurface_color, self.subsurface_radius, self.subsurface_anisotropy),
+                    SubsurfaceMode::Diffusion => diffusion_probe(rec, world, subsurface_color, self.subsurface_radius, r_in.time),
+                };
+                let (exit, attenuation) = match walk {
+                    Some((exit, throughput)) => (exit, throughput * diffuse_weight / (1.0 - specular_prob)),
+                    None => (entry, Color::black()),
+                };
+                return Some((exit, attenuation, emission, "subsurface".to_string()))
+            }
+
+            // diffuse transmission scatters to the far side, lights behind the surface are sampled too
+            if diffuse_transmission > random_float() {
+                let cosine_pdf = CosinePdf::new(-perturbed_normal);
+                let direct = random_float() < light_prob;
+                let direction = if direct {light_pdf.generate()} else {cosine_pdf.generate()};
+                let cosine_pdf_val = cosine_pdf.value(&direction) * (1.0 - light_prob);
+                let light_pdf_val = light_pdf.value(&direction) * light_prob;
+                let (chosen_pdf, other_pdf) = if direct {(light_pdf_val, cosine_pdf_val)} else {(cosine_pdf_val, light_pdf_val)};
+                let weight = if chosen_pdf > 0.0 {power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+                let cosine = f64::max(-rec.normal.dot(&direction.normalize()), 0.0);
+                let attenuation = diffuse * diffuse_weight * (cosine / PI * weight) / (1.0 - specular_prob);
+                let scattered = Ray::new(rec.point, direction, r_in.time);
+                return Some((scattered, attenuation, emission, "translucent".to_string()))
+            }
+
             // diffuse
             let cosine_pdf = CosinePdf::new(perturbed_normal);
             let mut scattered = Ray::new(rec.point, cosine_pdf.generate(), r_in.time);
 
             // directly sample lights half the time
-            let direct = random_float() > 0.5;
+            let direct = random_float() < light_prob;
             if direct {    
-                scattered.direction = to_light;               
+                scattered.direction = light_pdf.generate();               
             }
 
-            // compute weights
-            let light_pdf = LightPdf::new(lights.clone(), rec.point, perturbed_normal);
-            let cosine_pdf_val = cosine_pdf.value(&scattered.direction) * 0.5;
-            let light_pdf_val = light_pdf.value(&scattered.direction) * 0.5;
-            let mut pdf = Principle::scatter_pdf(&r_in, &rec, &scattered);
-            pdf = pdf / (cosine_pdf_val + light_pdf_val);
+            // one-sample mis between light and cosine sampling
+            let cosine_pdf_val = cosine_pdf.value(&scattered.direction) * (1.0 - light_prob);
+            let light_pdf_val = light_pdf.value(&scattered.direction) * light_prob;
+            let (chosen_pdf, other_pdf) = if direct {(light_pdf_val, cosine_pdf_val)} else {(cosine_pdf_val, light_pdf_val)};
+            let mut pdf = Principle::scatter_pdf(rec, &scattered);
+            pdf = if chosen_pdf > 0.0 {pdf * power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+            // rough diffuse replaces the lambert albedo with the eon response
+            let albedo = if diffuse_roughness > 0.0 {
+                eon_diffuse(diffuse, diffuse_roughness, perturbed_normal, -unit_direction, scattered.direction.normalize())
+            } else {
+                diffuse
+            };
 
             // final color composite
-            let attenuation = diffuse * diffuse_weight * pdf / (1.0 - specular_prob);
+            let attenuation = albedo * diffuse_weight * pdf / (1.0 - specular_prob);
 
-            return Some((scattered, attenuation, emission, "diffuse".to_string()))
-        }      
-        None     
-    } 
+            Some((scattered, attenuation, emission, "diffuse".to_string()))
+        }
+    }
 }
 
 
@@ -459,127 +809,620 @@ impl Light {
 }
 
 impl Emits for Light {
-    fn emit(&self) -> Color  {
+    fn emit(&self, _sp: &ShadingPoint) -> Color  {
         self.color * f64::powf(self.intensity, 2.0)
     }
 }
 
 impl Scatterable for Light {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lights: &Arc<Vec<Object>>) -> Option<(Ray, Color, Color, String)> {
-        Some((*r_in, Color::black(), self.emit(), "emission".to_string()))
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, _lights: &Arc<Vec<Object>>, _world: &Object) -> Option<(Ray, Color, Color, String)> {
+        Some((*r_in, Color::black(), self.emit(&ShadingPoint::new(rec, r_in)), "emission".to_string()))
     }
 }
 
 
+// blends two materials stochastically, a mask of 0 is all a and 1 is all b
+#[derive(Debug, Clone)]
+pub struct Mix {
+    pub a: Arc<Material>,
+    pub b: Arc<Material>,
+    pub mask: f64,
+    pub mask_texture: Option<Shader>,
+}
+
+impl Mix {
+    pub fn new(a: Arc<Material>, b: Arc<Material>, mask: f64, mask_texture: Option<Shader>) -> Mix {
+        Mix { a, b, mask, mask_texture }
+    }
+
+    pub fn mask_at(&self, sp: &ShadingPoint) -> f64 {
+        let mask = self.mask_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp).r)
+            .unwrap_or(self.mask);
+        clamp(mask, 0.0, 1.0)
+    }
 
-fn reflect(i: &Vec3, n: &Vec3) -> Vec3 {
-    return *i - *n * 2.0 *i.dot(n);
+    pub fn pick(&self, sp: &ShadingPoint) -> &Arc<Material> {
+        if random_float() < self.mask_at(sp) {&self.b} else {&self.a}
+    }
 }
 
+impl Scatterable for Mix {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lights: &Arc<Vec<Object>>, world: &Object) -> Option<(Ray, Color, Color, String)> {
+        self.pick(&ShadingPoint::new(rec, r_in)).scatter(r_in, rec, lights, world)
+    }
+}
 
-fn specular_pdf(cos_theta: f64, refraction_ratio: f64) -> f64 {
-    let fresnel = Principle::reflectance(cos_theta, refraction_ratio);
-    fresnel / std::f64::consts::PI
+// stacks a coat over a base. the coat scatters first with probability weight, whatever it sends
+// below the surface continues into the base tinted by the coat's transmission
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub coat: Arc<Material>,
+    pub base: Arc<Material>,
+    pub weight: f64,
+    pub weight_texture: Option<Shader>,
 }
 
-fn sample_lights(lights: &Arc<Vec<Object>>, point: Vec3) -> Vec3 {
-    let mut to_light = Vec3::zeros();
-    let mut sum_pdf = 0.0;
-    for (i, light) in lights.iter().enumerate() {
-        match light {
-            Object::QuadLight(quad_light) => {
-                let distance_squared = (quad_light.position - point).length_squared();
-                sum_pdf += quad_light.area / distance_squared;
-            }
-            _ => {}
-        }
+impl Layer {
+    pub fn new(coat: Arc<Material>, base: Arc<Material>, weight: f64, weight_texture: Option<Shader>) -> Layer {
+        Layer { coat, base, weight, weight_texture }
     }
 
-    let mut chosen_light = None;
-    for (i, light) in lights.iter().enumerate() {
-        match light {
-            Object::QuadLight(quad_light) => {
-                let distance_squared = (quad_light.position - point).length_squared();
-                let pdf = quad_light.area / distance_squared;
-                if chosen_light.is_none() && random_float() < pdf / sum_pdf {
-                    chosen_light = Some(quad_light);
-                }
-            }
-            _ => {}
+    pub fn weight_at(&self, sp: &ShadingPoint) -> f64 {
+        let weight = self.weight_texture
+            .as_ref()
+            .map(|t| t.evaluate(sp).r)
+            .unwrap_or(self.weight);
+        clamp(weight, 0.0, 1.0)
+    }
+}
+
+impl Scatterable for Layer {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lights: &Arc<Vec<Object>>, world: &Object) -> Option<(Ray, Color, Color, String)> {
+        let sp = ShadingPoint::new(rec, r_in);
+        // the coat covers a fraction weight of the surface and is picked with that probability,
+        // so the weight and the pick probability cancel in both branches
+        let coat_probability = if rec.front_face {self.weight_at(&sp)} else {0.0};
+        if coat_probability <= 0.0 || random_float() >= coat_probability {
+            return self.base.select(&sp).scatter(r_in, rec, lights, world)
         }
+
+        let (ray, coat_attenuation, emission, lobe) = self.coat.select(&sp).scatter(r_in, rec, lights, world)?;
+        if ray.direction.dot(&rec.normal) > 0.0 {
+            return Some((ray, coat_attenuation, emission, lobe))
+        }
+        // the coat's transmitted ray carries on into the base
+        let base_sp = ShadingPoint::new(rec, &ray);
+        let (ray, base_attenuation, base_emission, lobe) = self.base.select(&base_sp).scatter(&ray, rec, lights, world)?;
+        let mut attenuation = coat_attenuation * base_attenuation;
+        attenuation.a = 1.0;
+        let mut emission = emission + coat_attenuation * base_emission;
+        emission.a = 1.0;
+        Some((ray, attenuation, emission, lobe))
     }
+}
+
+
+// exact unpolarized fresnel, eta is the transmitted over incident ior
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = clamp(cos_i, 0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// exact unpolarized fresnel for a conductor with complex ior eta + ik
+fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = clamp(cos_i, 0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cos_i;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+// phase shift of the s polarized reflection off a conductor, used for the film interference
+fn fresnel_conductor_phase(cos_i: f64, eta_i: f64, eta: f64, k: f64) -> f64 {
+    let sin2 = 1.0 - cos_i * cos_i;
+    let a = eta * eta - k * k - eta_i * eta_i * sin2;
+    let b = (a * a + 4.0 * eta * eta * k * k).sqrt();
+    let u = (0.5 * (a + b)).max(0.0).sqrt();
+    let v = (0.5 * (b - a)).max(0.0).sqrt();
+    f64::atan2(2.0 * eta_i * v * cos_i, u * u + v * v - (eta_i * cos_i).powi(2))
+}
+
+// gulbrandsen 2014, maps normal reflectivity and edge tint to a complex ior per channel
+fn edge_tint_to_ior(reflectivity: Color, edge_tint: Color) -> (Color, Color) {
+    let fit = |r: f64, g: f64| {
+        let r = clamp(r, 0.0, 0.99);
+        let g = clamp(g, 0.0, 1.0);
+        let n_min = (1.0 - r) / (1.0 + r);
+        let n_max = (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+        let n = g * n_min + (1.0 - g) * n_max;
+        let k2 = ((n + 1.0).powi(2) * r - (n - 1.0).powi(2)) / (1.0 - r);
+        (n, k2.max(0.0).sqrt())
+    };
+    let (r, g, b) = (
+        fit(reflectivity.r, edge_tint.r),
+        fit(reflectivity.g, edge_tint.g),
+        fit(reflectivity.b, edge_tint.b),
+    );
+    (Color::new(r.0, g.0, b.0, 1.0), Color::new(r.1, g.1, b.1, 1.0))
+}
 
-    // generate scatter direction based on pdf
-    if let Some(quad_light) = chosen_light {
-        // Generate a random point on the selected light
-        let (s, t) = (random_float(), random_float());
-        let on_light = quad_light.position
-            + quad_light.x_axis * (s - 0.5) * quad_light.width
-            + quad_light.y_axis * (t - 0.5) * quad_light.height;  
+// complex ior of common metals at roughly 650, 550 and 450nm
+pub fn conductor_preset(name: &str) -> Option<(Color, Color)> {
+    let (eta, k) = match name.to_lowercase().as_str() {
+        "gold" => ([0.18299, 0.42108, 1.37340], [3.42420, 2.34590, 1.77040]),
+        "silver" => ([0.15943, 0.14512, 0.13547], [3.92910, 3.19000, 2.38080]),
+        "copper" => ([0.27105, 0.67693, 1.31640], [3.60920, 2.62480, 2.29210]),
+        "aluminum" | "aluminium" => ([1.34560, 0.96521, 0.61722], [7.47460, 6.39950, 5.30310]),
+        "chrome" | "chromium" => ([3.10710, 3.18120, 2.32300], [3.33140, 3.32910, 3.13500]),
+        "iron" => ([2.91140, 2.94970, 2.58450], [3.08930, 2.93180, 2.76700]),
+        "titanium" => ([2.74070, 2.54180, 2.26700], [3.81430, 3.43450, 3.03850]),
+        "platinum" => ([2.37570, 2.08470, 1.84530], [4.26550, 3.71530, 3.13650]),
+        "brass" => ([0.44400, 0.52700, 1.09400], [3.69500, 2.76500, 1.82900]),
+        _ => return None,
+    };
+    Some((Color::new(eta[0], eta[1], eta[2], 1.0), Color::new(k[0], k[1], k[2], 1.0)))
+}
 
-        // Compute the direction to the random point on the light
-        let to_light = on_light - point;    
-    }   
-    return to_light.normalize()
+// belcour and barla 2017 airy reflectance of a thin film over a base, thickness is in nm
+// and the base's complex ior is per channel, both relative to the outside medium
+fn thin_film_fresnel(cos_i: f64, thickness: f64, film_ior: f64, base_eta: [f64; 3], base_k: [f64; 3]) -> Color {
+    // fade the film out below 30nm so a vanishing film gives back the base fresnel
+    let fade = clamp(thickness / 30.0, 0.0, 1.0);
+    let film_ior = 1.0 + (film_ior - 1.0) * fade * fade * (3.0 - 2.0 * fade);
+    let cos_i = clamp(cos_i, 0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (film_ior * film_ior);
+    if sin2_t >= 1.0 {
+        return Color::white()
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    // outer interface, reflecting off a denser layer flips the phase
+    let r12 = fresnel_dielectric(cos_i, film_ior);
+    let t121 = 1.0 - r12;
+    let phi21 = if film_ior < 1.0 {0.0} else {PI};
+    let opd = 2.0 * film_ior * thickness * cos_t;
+
+    let mut rgb = [0.0; 3];
+    for (c, value) in rgb.iter_mut().enumerate() {
+        let r23 = fresnel_conductor(cos_t, base_eta[c] / film_ior, base_k[c] / film_ior);
+        let phi23 = fresnel_conductor_phase(cos_t, film_ior, base_eta[c], base_k[c]);
+        let r123 = clamp(r12 * r23, 1e-5, 0.9999);
+        let rs = t121 * t121 * r23 / (1.0 - r123);
+
+        // mean reflectance plus the first two interference orders
+        *value = r12 + rs;
+        let mut cm = rs - t121;
+        for m in 1..=2 {
+            cm *= r123.sqrt();
+            let m = m as f64;
+            *value += cm * 2.0 * film_sensitivity(m * opd, m * (phi21 + phi23))[c];
+        }
+        *value = value.max(0.0);
+    }
+    Color::new(rgb[0], rgb[1], rgb[2], 1.0)
 }
 
-fn ggx_distribution(ndh: f64, roughness: f64) -> f64 {
-    let a2: f64 = (roughness * roughness);
-    let d: f64 = ((ndh * a2 - ndh) * ndh + 1.0);//ndh * ndh * (a2 -1.0 ) + 1.0;
-    return a2 / (d * d * PI)
+// fourier transform of the cie matching functions as gaussian fits, evaluated at an
+// optical path difference in nm and converted to linear srgb
+fn film_sensitivity(opd: f64, shift: f64) -> [f64; 3] {
+    let phase = 2.0 * PI * opd * 1.0e-9;
+    let gaussian = |val: f64, pos: f64, var: f64| {
+        val * (2.0 * PI * var).sqrt() * (pos * phase + shift).cos() * (-phase * phase * var).exp()
+    };
+    let x = gaussian(5.4856e-13, 1.6810e+06, 4.3278e+09) + gaussian(9.7470e-14, 2.2399e+06, 4.5282e+09);
+    let y = gaussian(4.4201e-13, 1.7953e+06, 9.3046e+09);
+    let z = gaussian(5.2481e-13, 2.2084e+06, 6.6121e+09);
+    let (x, y, z) = (x / 1.0685e-7, y / 1.0685e-7, z / 1.0685e-7);
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+// smith lambda for ggx, height-correlated masking-shadowing is built from it
+pub fn smith_lambda(ndv: f64, alpha: f64) -> f64 {
+    let cos2 = ndv * ndv;
+    if cos2 <= 0.0 {
+        return INF
+    }
+    let tan2 = (1.0 - cos2) / cos2;
+    0.5 * (-1.0 + (1.0 + alpha * alpha * tan2).sqrt())
 }
 
-fn schlick_masking(ndl: f64, ndv: f64, roughness: f64) -> f64 {
-    let k: f64 = roughness * roughness / 2.0;
-    let g_v: f64 = ndv / (ndv * (1.0 - k) + k);
-    let g_l: f64 = ndl / (ndl * (1.0 - k) + k);
-    return g_v * g_l
+pub fn smith_g1(ndv: f64, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(ndv, alpha))
 }
 
-fn schlick_masking_alt(ndl: f64, ndv: f64, roughness: f64) -> f64 {
-    let a2: f64 = roughness * roughness;
-    let g_v: f64 = ndl * (ndv * ndv * (1.0 - a2) + a2).sqrt();
-    let g_l: f64 = ndv * (ndl * ndl * (1.0 - a2) + a2).sqrt();
-    return 0.5 / (g_v + g_l)
+pub fn smith_g2(ndv: f64, ndl: f64, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(ndv, alpha) + smith_lambda(ndl, alpha))
 }
 
 fn schlick_fresnel(f0: Vec3, ldh: f64) -> Color {
     let f = f0 + (Vec3::ones() - f0) * (1.0 - ldh).powf(5.0);
-    return Color::new(f.x, f.y, f.z, 1.0)
+    Color::new(f.x, f.y, f.z, 1.0)
 }
 
-fn ggx_sample(roughness: f64, normal: Vec3) -> Vec3 {
-    let (u, v) = (random_float(), random_float());
-    let b: Vec3 = get_perpendicular(normal);
-    let t: Vec3 = Vec3::cross(&b, &normal);
-    let a2 = roughness * roughness;
-    let cos_theta: f64 = (f64::max(0.0, (1.0 - u) / ((a2 - 1.0) * u + 1.0))).sqrt();
-    let sin_theta: f64 = (f64::max(0.0, 1.0 - cos_theta * cos_theta)).sqrt();
-    let phi = v * PI * 2.0;
-    let direction = (t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + normal * cos_theta);
+// burley's remap of roughness and anisotropy to tangent and bitangent alphas
+pub fn anisotropic_alpha(roughness: f64, anisotropy: f64) -> (f64, f64) {
+    let aspect = (1.0 - 0.9 * clamp(anisotropy, 0.0, 1.0)).sqrt();
+    let alpha = f64::max(roughness, 0.001);
+    (f64::max(alpha / aspect, 0.001), f64::max(alpha * aspect, 0.001))
+}
 
-    direction
+// anisotropic ggx terms, vectors are in the local tangent frame with z along the normal
+pub fn ggx_distribution_aniso(h: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if h.z <= 0.0 {
+        return 0.0
+    }
+    let t = (h.x / alpha_x).powi(2) + (h.y / alpha_y).powi(2) + h.z * h.z;
+    1.0 / (PI * alpha_x * alpha_y * t * t)
 }
 
-fn get_perpendicular(vec: Vec3) -> Vec3 {
-    let mut smallest = 0;
-    let mut perp = vec;
-    let v = [vec.x, vec.y, vec.z];
-    for i in 1..3 {
-        if v[i].abs() < v[smallest].abs() {
-            smallest = i;
-        }
+pub fn smith_lambda_aniso(v: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    let cos2 = v.z * v.z;
+    if cos2 <= 0.0 {
+        return INF
+    }
+    let a2_tan2 = ((alpha_x * v.x).powi(2) + (alpha_y * v.y).powi(2)) / cos2;
+    0.5 * (-1.0 + (1.0 + a2_tan2).sqrt())
+}
+
+pub fn smith_g1_aniso(v: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda_aniso(v, alpha_x, alpha_y))
+}
+
+pub fn smith_g2_aniso(v: Vec3, l: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda_aniso(v, alpha_x, alpha_y) + smith_lambda_aniso(l, alpha_x, alpha_y))
+}
+
+// samples a light or a visible ggx normal with one-sample mis, returns the direction,
+// the fresnel-free brdf * cosine / pdf and l.h for the caller's fresnel
+fn sample_ggx_lobe(
+    v: Vec3,
+    uvw: &Onb,
+    alpha_x: f64,
+    alpha_y: f64,
+    light_pdf: &LightPdf,
+    light_prob: f64
+    ) -> (Vec3, f64, f64) {
+    let v_local = uvw.to_local(v);
+    let sample_light = random_float() < light_prob;
+    let l = if sample_light {
+        light_pdf.generate()
+    } else {
+        let h = uvw.local(ggx_sample_vndf(v_local, alpha_x, alpha_y, random_float(), random_float()));
+        Vec3::reflect(-v, h).normalize()
+    };
+    let h = (v + l).normalize();
+    let (l_local, h_local) = (uvw.to_local(l), uvw.to_local(h));
+    if v_local.z <= 0.0 || l_local.z <= 0.0 {
+        return (l, 0.0, 0.0)
+    }
+
+    // ggx
+    let d = ggx_distribution_aniso(h_local, alpha_x, alpha_y);
+    let g = smith_g2_aniso(v_local, l_local, alpha_x, alpha_y);
+
+    // one-sample mis between light and brdf sampling
+    let bsdf_pdf = (1.0 - light_prob) * ggx_reflection_pdf(v_local, h_local, alpha_x, alpha_y);
+    let lights_pdf = light_prob * light_pdf.value(&l);
+    let (chosen_pdf, other_pdf) = if sample_light {(lights_pdf, bsdf_pdf)} else {(bsdf_pdf, lights_pdf)};
+    let weight = if chosen_pdf > 0.0 {power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+    (l, d * g / (4.0 * v_local.z) * weight, f64::max(l.dot(&h), 0.0))
+}
+
+// energy-preserving oren-nayar, portsmouth et al. 2024. fujii's single scattering form plus a
+// multiple scattering term built from its directional albedo, returned times pi so it stands
+// in for the lambert albedo
+fn eon_diffuse(rho: Color, roughness: f64, n: Vec3, v: Vec3, l: Vec3) -> Color {
+    let r = clamp(roughness, 0.0, 1.0);
+    let (mu_o, mu_i) = (n.dot(&v), n.dot(&l));
+    if mu_o <= 0.0 || mu_i <= 0.0 {
+        return Color::black()
+    }
+    let constant1 = 0.5 - 2.0 / (3.0 * PI);
+    let constant2 = 2.0 / 3.0 - 28.0 / (15.0 * PI);
+    let a = 1.0 / (1.0 + constant1 * r);
+
+    // single scattering
+    let s = l.dot(&v) - mu_i * mu_o;
+    let s_over_t = if s > 0.0 {s / mu_i.max(mu_o)} else {s};
+    let single = rho * (a * (1.0 + r * s_over_t));
+
+    // fitted directional albedo of the single scattering lobe and its average
+    let albedo = |mu: f64| {
+        let c = 1.0 - mu;
+        let g_over_pi = c * (0.0571085289 + c * (0.491881867 + c * (-0.332181442 + c * 0.0714429953)));
+        (1.0 + r * g_over_pi) * a
+    };
+    let average = a * (1.0 + constant2 * r);
+    let channel = |rho: f64| rho * rho * average / (1.0 - rho * (1.0 - average));
+    let eps = 1.0e-7;
+    let multiple = Color::new(channel(rho.r), channel(rho.g), channel(rho.b), 1.0)
+        * (f64::max(eps, 1.0 - albedo(mu_o)) * f64::max(eps, 1.0 - albedo(mu_i)) / f64::max(eps, 1.0 - average));
+
+    let mut color = single + multiple;
+    color.a = 1.0;
+    color
+}
+
+// estevez and kulla 2017 charlie sheen, inverted gaussian-like distribution with their fitted shadowing
+fn sheen_brdf(n: Vec3, v: Vec3, l: Vec3, roughness: f64) -> f64 {
+    let ndv = n.dot(&v);
+    let ndl = n.dot(&l);
+    if ndv <= 0.0 || ndl <= 0.0 {
+        return 0.0
     }
+    let h = (v + l).normalize();
+    let ndh = clamp(n.dot(&h), 0.0, 1.0);
+    let sin_h = (1.0 - ndh * ndh).sqrt();
+    let inv_r = 1.0 / roughness;
+    let d = (2.0 + inv_r) * sin_h.powf(inv_r) / (2.0 * PI);
+    let g = 1.0 / (1.0 + sheen_lambda(ndv, roughness) + sheen_lambda(ndl, roughness));
+    d * g / (4.0 * ndv * ndl)
+}
+
+fn sheen_lambda(cos_theta: f64, roughness: f64) -> f64 {
+    let fit = |x: f64| {
+        let r = 1.0 - (1.0 - roughness) * (1.0 - roughness);
+        let lerp = |a: f64, b: f64| a + (b - a) * r;
+        let (a, b, c) = (lerp(25.3245, 21.5473), lerp(3.32435, 3.82987), lerp(0.16801, 0.19823));
+        let (d, e) = (lerp(-1.27393, -1.97760), lerp(-4.85967, -4.32054));
+        a / (1.0 + b * x.powf(c)) + d * x + e
+    };
+    if cos_theta < 0.5 {
+        fit(cos_theta).exp()
+    } else {
+        (2.0 * fit(0.5) - fit(1.0 - cos_theta)).exp()
+    }
+}
+
+// kulla and conty 2017, the energy single scattering misses, 1 - E(v), goes to a diffuse-like
+// lobe picked with that probability. the weight leaves out fresnel, the multiple scattering
+// lobe takes multiple_scatter_fresnel instead of the fresnel at the half vector
+fn sample_ggx_lobe_ms(
+    v: Vec3,
+    uvw: &Onb,
+    alpha_x: f64,
+    alpha_y: f64,
+    light_pdf: &LightPdf,
+    light_prob: f64
+    ) -> (Vec3, f64, f64, bool) {
+    let n = uvw.w();
+    let alpha = (alpha_x * alpha_y).sqrt();
+    let e_v = ggx_albedo(n.dot(&v), alpha);
+    if random_float() < e_v {
+        let (l, ggx, ldh) = sample_ggx_lobe(v, uvw, alpha_x, alpha_y, light_pdf, light_prob);
+        return (l, ggx / e_v, ldh, false)
+    }
+
+    // one-sample mis between light and cosine sampling
+    let cosine_pdf = CosinePdf::new(n);
+    let direct = random_float() < light_prob;
+    let l = if direct {light_pdf.generate()} else {cosine_pdf.generate()}.normalize();
+    let ndl = n.dot(&l);
+    if ndl <= 0.0 {
+        return (l, 0.0, 0.0, true)
+    }
+    let cosine_pdf_val = cosine_pdf.value(&l) * (1.0 - light_prob);
+    let light_pdf_val = light_pdf.value(&l) * light_prob;
+    let (chosen_pdf, other_pdf) = if direct {(light_pdf_val, cosine_pdf_val)} else {(cosine_pdf_val, light_pdf_val)};
+    let weight = if chosen_pdf > 0.0 {power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+    // the 1 - E(v) in the brdf cancels with the lobe choice
+    let brdf = (1.0 - ggx_albedo(ndl, alpha)) / (PI * (1.0 - ggx_average_albedo(alpha)));
+    (l, brdf * ndl * weight, ndl, true)
+}
+
+// cosine weighted hemispherical average of a fresnel term
+fn average_fresnel(fresnel: impl Fn(f64) -> Color) -> Color {
+    let steps = 16;
+    let mut sum = Color::black();
+    for i in 0..steps {
+        let cos = (i as f64 + 0.5) / steps as f64;
+        sum = sum + fresnel(cos) * (2.0 * cos / steps as f64);
+    }
+    sum.a = 1.0;
+    sum
+}
+
+// fresnel of the multiple scattering lobe, every extra bounce reflects with the average fresnel
+fn multiple_scatter_fresnel(f_avg: Color, e_avg: f64) -> Color {
+    let channel = |f: f64| f * f * e_avg / (1.0 - f * (1.0 - e_avg));
+    Color::new(channel(f_avg.r), channel(f_avg.g), channel(f_avg.b), 1.0)
+}
+
+// directional albedo E(v) of single scattering ggx without fresnel, and its cosine weighted
+// average per roughness, integrated once over stratified visible normal samples
+const GGX_TABLE_SIZE: usize = 32;
+static GGX_ALBEDO: OnceLock<(Vec<f64>, Vec<f64>)> = OnceLock::new();
+
+fn ggx_albedo_tables() -> &'static (Vec<f64>, Vec<f64>) {
+    GGX_ALBEDO.get_or_init(|| {
+        let size = GGX_TABLE_SIZE;
+        let strata = 32;
+        let mut albedo = vec![0.0; size * size];
+        let mut average = vec![0.0; size];
+        for j in 0..size {
+            let alpha = f64::max(j as f64 / (size - 1) as f64, 0.001);
+            for i in 0..size {
+                let ndv = f64::max(i as f64 / (size - 1) as f64, 0.001);
+                let v = Vec3::new((1.0 - ndv * ndv).sqrt(), 0.0, ndv);
+
+                // under vndf sampling the estimator reduces to G2 / G1
+                let mut sum = 0.0;
+                for s in 0..strata * strata {
+                    let u1 = ((s % strata) as f64 + 0.5) / strata as f64;
+                    let u2 = ((s / strata) as f64 + 0.5) / strata as f64;
+                    let h = ggx_sample_vndf(v, alpha, alpha, u1, u2);
+                    let l = Vec3::reflect(-v, h);
+                    if l.z > 0.0 {
+                        sum += smith_g2(ndv, l.z, alpha) / smith_g1(ndv, alpha);
+                    }
+                }
+                albedo[j * size + i] = sum / (strata * strata) as f64;
+            }
+
+            // 2 * integral of E(mu) mu over the cosine, trapezoid over the table row
+            let row = &albedo[j * size..(j + 1) * size];
+            let step = 1.0 / (size - 1) as f64;
+            average[j] = (1..size)
+                .map(|i| {
+                    let (mu0, mu1) = ((i - 1) as f64 * step, i as f64 * step);
+                    (row[i - 1] * mu0 + row[i] * mu1) * step
+                })
+                .sum();
+        }
+        (albedo, average)
+    })
+}
+
+fn ggx_albedo(ndv: f64, alpha: f64) -> f64 {
+    let table = &ggx_albedo_tables().0;
+
+    // bilinear lookup
+    let scale = (GGX_TABLE_SIZE - 1) as f64;
+    let x = clamp(ndv, 0.0, 1.0) * scale;
+    let y = clamp(alpha, 0.0, 1.0) * scale;
+    let (x0, y0) = ((x as usize).min(GGX_TABLE_SIZE - 2), (y as usize).min(GGX_TABLE_SIZE - 2));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let at = |i: usize, j: usize| table[j * GGX_TABLE_SIZE + i];
+    let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+    let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+    clamp(top * (1.0 - fy) + bottom * fy, 0.0, 1.0)
+}
+
+fn ggx_average_albedo(alpha: f64) -> f64 {
+    let average = &ggx_albedo_tables().1;
+    let y = clamp(alpha, 0.0, 1.0) * (GGX_TABLE_SIZE - 1) as f64;
+    let y0 = (y as usize).min(GGX_TABLE_SIZE - 2);
+    let fy = y - y0 as f64;
+    clamp(average[y0] * (1.0 - fy) + average[y0 + 1] * fy, 0.0, 0.999)
+}
+
+// directional albedo of the sheen lobe, integrated once into a cos theta by roughness table
+const SHEEN_TABLE_SIZE: usize = 32;
+static SHEEN_ALBEDO: OnceLock<Vec<f64>> = OnceLock::new();
+
+fn sheen_albedo(ndv: f64, roughness: f64) -> f64 {
+    let table = SHEEN_ALBEDO.get_or_init(|| {
+        let size = SHEEN_TABLE_SIZE;
+        let steps = 64;
+        let mut table = vec![0.0; size * size];
+        for j in 0..size {
+            let roughness = f64::max(j as f64 / (size - 1) as f64, 0.01);
+            for i in 0..size {
+                let ndv = f64::max(i as f64 / (size - 1) as f64, 0.01);
+                let v = Vec3::new((1.0 - ndv * ndv).sqrt(), 0.0, ndv);
+                let n = Vec3::new(0.0, 0.0, 1.0);
+
+                // midpoint rule over cos theta and phi, dw = dcos dphi
+                let mut sum = 0.0;
+                for t in 0..steps {
+                    let ndl = (t as f64 + 0.5) / steps as f64;
+                    let sin_l = (1.0 - ndl * ndl).sqrt();
+                    for p in 0..steps {
+                        let phi = (p as f64 + 0.5) / steps as f64 * PI;
+                        let l = Vec3::new(sin_l * phi.cos(), sin_l * phi.sin(), ndl);
+                        sum += sheen_brdf(n, v, l, roughness) * ndl;
+                    }
+                }
+                table[j * size + i] = sum * 2.0 * PI / (steps * steps) as f64;
+            }
+        }
+        table
+    });
+
+    // bilinear lookup
+    let scale = (SHEEN_TABLE_SIZE - 1) as f64;
+    let x = clamp(ndv, 0.0, 1.0) * scale;
+    let y = clamp(roughness, 0.0, 1.0) * scale;
+    let (x0, y0) = ((x as usize).min(SHEEN_TABLE_SIZE - 2), (y as usize).min(SHEEN_TABLE_SIZE - 2));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let at = |i: usize, j: usize| table[j * SHEEN_TABLE_SIZE + i];
+    let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+    let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+    clamp(top * (1.0 - fy) + bottom * fy, 0.0, 1.0)
+}
 
-    let mut tmp = [0.0; 3];
-    tmp[smallest] = 1.0;
-    let tmp = Vec3::new(tmp[0], tmp[1], tmp[2]).normalize();
-    let dot_product = perp.x * tmp.x + perp.y * tmp.y + perp.z * tmp.z;
+// heitz 2018 visible normal sampling, view and result are in the local shading frame
+pub fn ggx_sample_vndf(v: Vec3, alpha_x: f64, alpha_y: f64, u1: f64, u2: f64) -> Vec3 {
+    let vh = Vec3::new(alpha_x * v.x, alpha_y * v.y, v.z).normalize();
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / lensq.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(&t1);
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * f64::max(0.0, 1.0 - p1 * p1 - p2 * p2).sqrt();
+    Vec3::new(alpha_x * nh.x, alpha_y * nh.y, f64::max(0.0, nh.z)).normalize()
+}
 
-    perp.x = perp.x - dot_product * tmp.x;
-    perp.y = perp.y - dot_product * tmp.y;
-    perp.z = perp.z - dot_product * tmp.z;
+// density of a visible normal
+pub fn ggx_vndf_pdf(v: Vec3, h: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if v.z <= 0.0 {
+        return 0.0
+    }
+    smith_g1_aniso(v, alpha_x, alpha_y) * f64::max(v.dot(&h), 0.0) * ggx_distribution_aniso(h, alpha_x, alpha_y) / v.z
+}
 
-    perp.normalize()
+// solid angle density of the reflected direction
+pub fn ggx_reflection_pdf(v: Vec3, h: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    let vdh = v.dot(&h);
+    if vdh <= 0.0 {
+        return 0.0
+    }
+    ggx_vndf_pdf(v, h, alpha_x, alpha_y) / (4.0 * vdh)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // white furnace, with F = 1 the compensated ggx lobe has to reflect everything it receives
+    #[test]
+    fn compensated_ggx_conserves_energy() {
+        let lights = Arc::new(Vec::new());
+        let light_pdf = LightPdf::new(lights, Vec3::zeros());
+        let mut uvw = Onb::new();
+        uvw.build_from_w(Vec3::new(0.0, 0.0, 1.0));
+        let samples = 100_000;
+        for alpha in [0.05, 0.25, 0.5, 0.75, 1.0] {
+            for ndv in [0.15f64, 0.5, 0.9] {
+                let v = Vec3::new((1.0 - ndv * ndv).sqrt(), 0.0, ndv);
+                let fresnel = multiple_scatter_fresnel(Color::white(), ggx_average_albedo(alpha)).r;
+                let albedo = (0..samples)
+                    .map(|_| {
+                        let (_, weight, _, multiple) = sample_ggx_lobe_ms(v, &uvw, alpha, alpha, &light_pdf, 0.0);
+                        if multiple {weight * fresnel} else {weight}
+                    })
+                    .sum::<f64>() / samples as f64;
+                assert!((albedo - 1.0).abs() < 0.03, "alpha {alpha}, n.v {ndv}: albedo {albedo}");
+            }
+        }
+    }
+}
@@ -7,6 +7,10 @@ mtlPluginNodeName = "krustyMaterial"
 mtlPluginNodeId = OpenMaya.MTypeId(0x00333)
 lightPluginNodeName = "krustyLight"
 lightPluginNodeId = OpenMaya.MTypeId(0x00000)
+mixPluginNodeName = "krustyMix"
+mixPluginNodeId = OpenMaya.MTypeId(0x00334)
+layerPluginNodeName = "krustyLayer"
+layerPluginNodeId = OpenMaya.MTypeId(0x00335)
 
 
 class krustyMaterial(OpenMayaMPx.MPxNode):
@@ -21,6 +25,8 @@ def mtlNodeCreator():
 
 def mtlNodeInitializer():
     nAttr = OpenMaya.MFnNumericAttribute()
+    eAttr = OpenMaya.MFnEnumAttribute()
+    tAttr = OpenMaya.MFnTypedAttribute()
     kFloat = OpenMaya.MFnNumericData.kFloat
 
     # input attributes
@@ -34,6 +40,25 @@ def mtlNodeInitializer():
     nAttr.setDefault(1.0, 1.0, 1.0)
     krustyMaterial.addAttribute(krustyMaterial.diffuseWeight)
 
+    krustyMaterial.diffuseRoughness = nAttr.createColor('diffuseRoughness', 'dr')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.diffuseRoughness)
+
+    krustyMaterial.opacity = nAttr.createColor('opacity', 'op')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyMaterial.addAttribute(krustyMaterial.opacity)
+
+    krustyMaterial.thinWalled = nAttr.create('thinWalled', 'thw', OpenMaya.MFnNumericData.kBoolean, False)
+    nAttr.setStorable(True)
+    krustyMaterial.addAttribute(krustyMaterial.thinWalled)
+
+    krustyMaterial.diffuseTransmission = nAttr.createColor('diffuseTransmission', 'dtr')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.diffuseTransmission)
+
     krustyMaterial.specular = nAttr.createColor('specular', 'spec')
     nAttr.setStorable(True)
     nAttr.setDefault(1.0, 1.0, 1.0)
@@ -49,6 +74,16 @@ def mtlNodeInitializer():
     nAttr.setDefault(0.4, 0.4, 0.4)
     krustyMaterial.addAttribute(krustyMaterial.roughness)
 
+    krustyMaterial.anisotropy = nAttr.createColor('anisotropy', 'aniso')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.anisotropy)
+
+    krustyMaterial.anisotropyRotation = nAttr.createColor('anisotropyRotation', 'anisor')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.anisotropyRotation)
+
     krustyMaterial.ior = nAttr.create('IOR', 'ior', kFloat, 1.5)
     nAttr.setStorable(True)
     nAttr.setMin(1.01)
@@ -65,6 +100,162 @@ def mtlNodeInitializer():
     nAttr.setDefault(0.0, 0.0, 0.0)
     krustyMaterial.addAttribute(krustyMaterial.refraction)
 
+    krustyMaterial.transmission = nAttr.createColor('transmission', 'trans')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyMaterial.addAttribute(krustyMaterial.transmission)
+
+    krustyMaterial.transmissionDepth = nAttr.create('transmissionDepth', 'td', kFloat, 0.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(0.0)
+    krustyMaterial.addAttribute(krustyMaterial.transmissionDepth)
+
+    krustyMaterial.clearcoat = nAttr.createColor('clearcoat', 'cc')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.clearcoat)
+
+    krustyMaterial.clearcoatRoughness = nAttr.createColor('clearcoatRoughness', 'ccr')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.1, 0.1, 0.1)
+    krustyMaterial.addAttribute(krustyMaterial.clearcoatRoughness)
+
+    krustyMaterial.clearcoatIor = nAttr.create('clearcoatIor', 'ccior', kFloat, 1.5)
+    nAttr.setStorable(True)
+    nAttr.setMin(1.01)
+    nAttr.setMax(3)
+    krustyMaterial.addAttribute(krustyMaterial.clearcoatIor)
+
+    krustyMaterial.clearcoatNormal = nAttr.createColor('clearcoatNormal', 'ccn')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.clearcoatNormal)
+
+    krustyMaterial.thinFilmThickness = nAttr.create('thinFilmThickness', 'tft', kFloat, 0.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(0.0)
+    nAttr.setSoftMax(1000.0)
+    krustyMaterial.addAttribute(krustyMaterial.thinFilmThickness)
+
+    krustyMaterial.thinFilmIor = nAttr.create('thinFilmIor', 'tfior', kFloat, 1.33)
+    nAttr.setStorable(True)
+    nAttr.setMin(1.0)
+    nAttr.setMax(3)
+    krustyMaterial.addAttribute(krustyMaterial.thinFilmIor)
+
+    krustyMaterial.metalEdgeTint = nAttr.createColor('metalEdgeTint', 'met')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyMaterial.addAttribute(krustyMaterial.metalEdgeTint)
+
+    krustyMaterial.metalPreset = eAttr.create('metalPreset', 'mpre', 0)
+    for i, preset in enumerate(['edgeTint', 'complexIor', 'gold', 'silver', 'copper', 'aluminum', 'chrome', 'iron', 'titanium', 'platinum', 'brass']):
+        eAttr.addField(preset, i)
+    eAttr.setStorable(True)
+    krustyMaterial.addAttribute(krustyMaterial.metalPreset)
+
+    krustyMaterial.metalEta = nAttr.createColor('metalEta', 'meta')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.2, 0.4, 1.4)
+    krustyMaterial.addAttribute(krustyMaterial.metalEta)
+
+    krustyMaterial.metalK = nAttr.createColor('metalK', 'mk')
+    nAttr.setStorable(True)
+    nAttr.setDefault(3.4, 2.3, 1.8)
+    krustyMaterial.addAttribute(krustyMaterial.metalK)
+
+    krustyMaterial.sheen = nAttr.createColor('sheen', 'sh')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.sheen)
+
+    krustyMaterial.sheenColor = nAttr.createColor('sheenColor', 'shc')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyMaterial.addAttribute(krustyMaterial.sheenColor)
+
+    krustyMaterial.sheenRoughness = nAttr.createColor('sheenRoughness', 'shr')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.3, 0.3, 0.3)
+    krustyMaterial.addAttribute(krustyMaterial.sheenRoughness)
+
+    krustyMaterial.subsurface = nAttr.createColor('subsurface', 'sss')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.subsurface)
+
+    krustyMaterial.subsurfaceColor = nAttr.createColor('subsurfaceColor', 'sssc')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyMaterial.addAttribute(krustyMaterial.subsurfaceColor)
+
+    krustyMaterial.subsurfaceRadius = nAttr.createColor('subsurfaceRadius', 'sssr')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 0.2, 0.1)
+    krustyMaterial.addAttribute(krustyMaterial.subsurfaceRadius)
+
+    krustyMaterial.subsurfaceAnisotropy = nAttr.create('subsurfaceAnisotropy', 'sssa', kFloat, 0.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(-0.99)
+    nAttr.setMax(0.99)
+    krustyMaterial.addAttribute(krustyMaterial.subsurfaceAnisotropy)
+
+    krustyMaterial.subsurfaceMode = eAttr.create('subsurfaceMode', 'sssm', 0)
+    eAttr.addField('randomWalk', 0)
+    eAttr.addField('diffusion', 1)
+    eAttr.setStorable(True)
+    krustyMaterial.addAttribute(krustyMaterial.subsurfaceMode)
+
+    krustyMaterial.volume = nAttr.create('volume', 'vol', OpenMaya.MFnNumericData.kBoolean, False)
+    nAttr.setStorable(True)
+    krustyMaterial.addAttribute(krustyMaterial.volume)
+
+    krustyMaterial.mediumAbsorption = nAttr.createColor('mediumAbsorption', 'mabs')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.mediumAbsorption)
+
+    krustyMaterial.mediumScattering = nAttr.createColor('mediumScattering', 'mscat')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.mediumScattering)
+
+    krustyMaterial.mediumEmission = nAttr.createColor('mediumEmission', 'memit')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.0, 0.0, 0.0)
+    krustyMaterial.addAttribute(krustyMaterial.mediumEmission)
+
+    krustyMaterial.mediumAnisotropy = nAttr.create('mediumAnisotropy', 'maniso', kFloat, 0.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(-0.99)
+    nAttr.setMax(0.99)
+    krustyMaterial.addAttribute(krustyMaterial.mediumAnisotropy)
+
+    krustyMaterial.mediumDensity = nAttr.create('mediumDensity', 'mdens', kFloat, 1.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(0.0)
+    krustyMaterial.addAttribute(krustyMaterial.mediumDensity)
+
+    krustyMaterial.mediumGrid = tAttr.create('mediumGrid', 'mgrid', OpenMaya.MFnData.kString)
+    tAttr.setStorable(True)
+    tAttr.setUsedAsFilename(True)
+    krustyMaterial.addAttribute(krustyMaterial.mediumGrid)
+
+    krustyMaterial.mediumTemperature = tAttr.create('mediumTemperature', 'mtemp', OpenMaya.MFnData.kString)
+    tAttr.setStorable(True)
+    tAttr.setUsedAsFilename(True)
+    krustyMaterial.addAttribute(krustyMaterial.mediumTemperature)
+
+    krustyMaterial.temperatureScale = nAttr.create('temperatureScale', 'tscale', kFloat, 1.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(0.0)
+    krustyMaterial.addAttribute(krustyMaterial.temperatureScale)
+
+    krustyMaterial.blackbodyIntensity = nAttr.create('blackbodyIntensity', 'bbi', kFloat, 1.0)
+    nAttr.setStorable(True)
+    nAttr.setMin(0.0)
+    krustyMaterial.addAttribute(krustyMaterial.blackbodyIntensity)
+
     krustyMaterial.emission = nAttr.createColor('emission', 'e')
     nAttr.setStorable(True)
     nAttr.setDefault(0.0, 0.0, 0.0)
@@ -80,6 +271,9 @@ def mtlNodeInitializer():
     nAttr.setDefault(0.0, 0.0, 0.0)
     krustyMaterial.addAttribute(krustyMaterial.normal)
 
+    # world space height, in scene units, of a bump value of one. the surface is displaced along
+    # its normal by bump value * bumpStrength, so a texture ranging 0-1 with bumpStrength 0.01 gives
+    # bumps one hundredth of a unit high. this used to scale the bump gradient, older scenes need re-tuning
     krustyMaterial.bumpStrength = nAttr.create('bumpStrength', 'bs', kFloat, 1.0)
     nAttr.setStorable(True)
     krustyMaterial.addAttribute(krustyMaterial.bumpStrength)
@@ -119,8 +313,63 @@ def lightNodeInitializer():
     krustyLight.addAttribute(krustyLight.intensity)
 
 
+class krustyMix(OpenMayaMPx.MPxNode):
+
+    def __init__(self):
+        OpenMayaMPx.MPxNode.__init__(self)
+
+
+def mixNodeCreator():
+    return OpenMayaMPx.asMPxPtr(krustyMix())
+
+
+def mixNodeInitializer():
+    nAttr = OpenMaya.MFnNumericAttribute()
+    mAttr = OpenMaya.MFnMessageAttribute()
+
+    # materials are connected by message, a mask of 0 is all A and 1 is all B
+    krustyMix.materialA = mAttr.create('materialA', 'ma')
+    krustyMix.addAttribute(krustyMix.materialA)
+
+    krustyMix.materialB = mAttr.create('materialB', 'mb')
+    krustyMix.addAttribute(krustyMix.materialB)
+
+    krustyMix.mask = nAttr.createColor('mask', 'msk')
+    nAttr.setStorable(True)
+    nAttr.setDefault(0.5, 0.5, 0.5)
+    krustyMix.addAttribute(krustyMix.mask)
+
+
+class krustyLayer(OpenMayaMPx.MPxNode):
+
+    def __init__(self):
+        OpenMayaMPx.MPxNode.__init__(self)
+
+
+def layerNodeCreator():
+    return OpenMayaMPx.asMPxPtr(krustyLayer())
+
+
+def layerNodeInitializer():
+    nAttr = OpenMaya.MFnNumericAttribute()
+    mAttr = OpenMaya.MFnMessageAttribute()
+
+    krustyLayer.coat = mAttr.create('coat', 'ct')
+    krustyLayer.addAttribute(krustyLayer.coat)
+
+    krustyLayer.base = mAttr.create('base', 'bs')
+    krustyLayer.addAttribute(krustyLayer.base)
+
+    krustyLayer.weight = nAttr.createColor('weight', 'wt')
+    nAttr.setStorable(True)
+    nAttr.setDefault(1.0, 1.0, 1.0)
+    krustyLayer.addAttribute(krustyLayer.weight)
+
+
 all_nodes = [[mtlPluginNodeName, mtlPluginNodeId, mtlNodeCreator, mtlNodeInitializer],
-             [lightPluginNodeName, lightPluginNodeId, lightNodeCreator, lightNodeInitializer], ]
+             [lightPluginNodeName, lightPluginNodeId, lightNodeCreator, lightNodeInitializer],
+             [mixPluginNodeName, mixPluginNodeId, mixNodeCreator, mixNodeInitializer],
+             [layerPluginNodeName, layerPluginNodeId, layerNodeCreator, layerNodeInitializer], ]
 
 
 def initializePlugin(mobject):
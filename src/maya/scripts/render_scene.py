@@ -41,7 +41,7 @@ def get_meshes():
             obj_nodes = cmds.listHistory(obj)
             for connected_node in obj_nodes:
                 if cmds.nodeType(connected_node) == 'mesh' and  cmds.getAttr('%s.visibility' % obj) == True:
-                    vtx_p, vtx_n, vtx_uv = get_vtx(obj)
+                    vtx_p, vtx_n, vtx_uv, vtx_object = get_vtx(obj)
                     try:
                         mat = cmds.getAttr(obj + '.krrustyMaterial')
                     except:
@@ -51,6 +51,7 @@ def get_meshes():
                         "vertices": vtx_p,
                         "normals": vtx_n,
                         "uvs": vtx_uv,
+                        "object_vertices": vtx_object,
                         "material": mat
                     }
                     mesh_objects.append(mesh)
@@ -111,7 +112,7 @@ def get_vtx(node):
     selList.getDagPath(0, dagPath)
     meshFn = OpenMaya.MFnMesh(dagPath)
 
-    out_vtx_p, out_vtx_n, out_vtx_uv = [], [], []
+    out_vtx_p, out_vtx_n, out_vtx_uv, out_vtx_object = [], [], [], []
     meshVtxCount = OpenMaya.MIntArray()
     meshVtxArray = OpenMaya.MIntArray() 
 
@@ -127,23 +128,27 @@ def get_vtx(node):
     uvIndex = 0
     for i, count in enumerate(meshVtxCount):
         faceVtx = meshVtxArray[uvIndex: uvIndex + count]
-        p, n, uv = [], [], []
+        p, n, uv, o = [], [], [], []
         for j, id in enumerate(faceVtx):
             vtxPosition = OpenMaya.MPoint()
+            vtxObject = OpenMaya.MPoint()
             vtxNormal = OpenMaya.MVector()
             meshFn.getPoint(id, vtxPosition, OpenMaya.MSpace.kWorld)
+            meshFn.getPoint(id, vtxObject, OpenMaya.MSpace.kObject)
             meshFn.getVertexNormal(id, True, vtxNormal, OpenMaya.MSpace.kWorld)
             u_val = u_array[uvIds[uvIndex + j]]
             v_val = v_array[uvIds[uvIndex + j]]
             p.append([vtxPosition[0], vtxPosition[1], vtxPosition[2]])
             n.append([vtxNormal[0], vtxNormal[1], vtxNormal[2]])
             uv.append([u_val, v_val])
+            o.append([vtxObject[0], vtxObject[1], vtxObject[2]])
         out_vtx_p.append(p)
         out_vtx_n.append(n)
         out_vtx_uv.append(uv)
+        out_vtx_object.append(o)
         uvIndex += count
 
-    return out_vtx_p, out_vtx_n, out_vtx_uv
+    return out_vtx_p, out_vtx_n, out_vtx_uv, out_vtx_object
 
 
 def get_materials():
@@ -154,67 +159,102 @@ def get_materials():
         diffuse = cmds.getAttr(m+'.diffuse')[0]
         # diffuse = [to_linear(diffuse[0]), to_linear(diffuse[1]), to_linear(diffuse[2])]
         diffuse_weight = cmds.getAttr(m+'.diffuseWeight')[0]
+        diffuse_roughness = cmds.getAttr(m+'.diffuseRoughness')[0]
+        opacity = cmds.getAttr(m+'.opacity')[0]
+        thin_walled = cmds.getAttr(m+'.thinWalled')
+        diffuse_transmission = cmds.getAttr(m+'.diffuseTransmission')[0]
         specular = cmds.getAttr(m+'.specular')[0]
         specular_weight = cmds.getAttr(m+'.specularWeight')[0]
         roughness = cmds.getAttr(m+'.roughness')[0]
+        anisotropy = cmds.getAttr(m+'.anisotropy')[0]
+        anisotropy_rotation = cmds.getAttr(m+'.anisotropyRotation')[0]
         metallic = cmds.getAttr(m+'.metallic')[0]
         refraction = cmds.getAttr(m+'.refraction')[0]
+        transmission = cmds.getAttr(m+'.transmission')[0]
+        transmission_depth = cmds.getAttr(m+'.transmissionDepth')
+        clearcoat = cmds.getAttr(m+'.clearcoat')[0]
+        clearcoat_roughness = cmds.getAttr(m+'.clearcoatRoughness')[0]
+        clearcoat_ior = cmds.getAttr(m+'.clearcoatIor')
+        thin_film_thickness = cmds.getAttr(m+'.thinFilmThickness')
+        thin_film_ior = cmds.getAttr(m+'.thinFilmIor')
+        metal_edge_tint = cmds.getAttr(m+'.metalEdgeTint')[0]
+        metal_preset = cmds.getAttr(m+'.metalPreset', asString=True)
+        metal_eta = cmds.getAttr(m+'.metalEta')[0]
+        metal_k = cmds.getAttr(m+'.metalK')[0]
+        sheen = cmds.getAttr(m+'.sheen')[0]
+        sheen_color = cmds.getAttr(m+'.sheenColor')[0]
+        sheen_roughness = cmds.getAttr(m+'.sheenRoughness')[0]
+        subsurface = cmds.getAttr(m+'.subsurface')[0]
+        subsurface_color = cmds.getAttr(m+'.subsurfaceColor')[0]
+        subsurface_radius = cmds.getAttr(m+'.subsurfaceRadius')[0]
+        subsurface_anisotropy = cmds.getAttr(m+'.subsurfaceAnisotropy')
+        subsurface_mode = cmds.getAttr(m+'.subsurfaceMode')
+        volume = cmds.getAttr(m+'.volume')
+        medium_absorption = cmds.getAttr(m+'.mediumAbsorption')[0]
+        medium_scattering = cmds.getAttr(m+'.mediumScattering')[0]
+        medium_emission = cmds.getAttr(m+'.mediumEmission')[0]
+        medium_anisotropy = cmds.getAttr(m+'.mediumAnisotropy')
+        medium_density = cmds.getAttr(m+'.mediumDensity')
+        medium_grid = cmds.getAttr(m+'.mediumGrid') or ''
+        medium_temperature = cmds.getAttr(m+'.mediumTemperature') or ''
+        temperature_scale = cmds.getAttr(m+'.temperatureScale')
+        blackbody_intensity = cmds.getAttr(m+'.blackbodyIntensity')
         emission = cmds.getAttr(m+'.emission')[0]
-        bump = cmds.getAttr(m+'.bump')[0]
         bump_strength = cmds.getAttr(m+'.bumpStrength')
         normal_strength = cmds.getAttr(m+'.normalStrength')
         ior = cmds.getAttr(m+'.ior')
     
         # textures
-        diffuse_tex = ''
-        dt = cmds.listConnections(m+'.diffuse', type='file')
-        if dt:
-            diffuse_tex = cmds.getAttr(dt[0] + '.fileTextureName')
-
-        diffuse_weight_tex = ''
-        dwt = cmds.listConnections(m+'.diffuseWeight', type='file')
-        if dwt:
-            diffuse_weight_tex = cmds.getAttr(dwt[0] + '.fileTextureName')
-
-        specular_tex = ''
-        st = cmds.listConnections(m+'.specular', type='file')
-        if st:
-            specular_tex = cmds.getAttr(st[0] + '.fileTextureName')
-
-        specular_weight_tex = ''
-        swt = cmds.listConnections(m+'.specularWeight', type='file')
-        if swt:
-            specular_weight_tex = cmds.getAttr(swt[0] + '.fileTextureName')
-
-        roughness_tex = ''
-        rt = cmds.listConnections(m+'.roughness', type='file')
-        if rt:
-            roughness_tex = cmds.getAttr(rt[0] + '.fileTextureName')
-            
-        metallic_tex = ''
-        mt = cmds.listConnections(m+'.metallic', type='file')
-        if mt:
-            metallic_tex = cmds.getAttr(mt[0] + '.fileTextureName')
-
-        refraction_tex = ''
-        rft = cmds.listConnections(m+'.refraction', type='file')
-        if rft:
-            refraction_tex = cmds.getAttr(rft[0] + '.fileTextureName')
-
-        emission_tex = ''
-        et = cmds.listConnections(m+'.emission', type='file')
-        if et:
-            emission_tex = cmds.getAttr(et[0] + '.fileTextureName')
-
-        bump_tex = ''
-        bt = cmds.listConnections(m+'.bump', type='file')
-        if bt:
-            bump_tex = cmds.getAttr(bt[0] + '.fileTextureName')
-
-        normal_tex = ''
-        nt = cmds.listConnections(m+'.normal', type='file')
-        if nt:
-            normal_tex = cmds.getAttr(nt[0] + '.fileTextureName')
+        diffuse_tex = get_texture(m+'.diffuse')
+        diffuse_weight_tex = get_texture(m+'.diffuseWeight')
+        diffuse_roughness_tex = get_texture(m+'.diffuseRoughness')
+        opacity_tex = get_texture(m+'.opacity')
+        diffuse_transmission_tex = get_texture(m+'.diffuseTransmission')
+        specular_tex = get_texture(m+'.specular')
+        specular_weight_tex = get_texture(m+'.specularWeight')
+        roughness_tex = get_texture(m+'.roughness')
+        anisotropy_tex = get_texture(m+'.anisotropy')
+        anisotropy_rotation_tex = get_texture(m+'.anisotropyRotation')
+        metallic_tex = get_texture(m+'.metallic')
+        refraction_tex = get_texture(m+'.refraction')
+        clearcoat_tex = get_texture(m+'.clearcoat')
+        clearcoat_roughness_tex = get_texture(m+'.clearcoatRoughness')
+        clearcoat_normal_tex = get_texture(m+'.clearcoatNormal', normal_map=True)
+        thin_film_thickness_tex = get_texture(m+'.thinFilmThickness')
+        metal_edge_tint_tex = get_texture(m+'.metalEdgeTint')
+
+        # edge tint and complex ior aren't presets, the renderer reads them from their own keys
+        if metal_preset in ('edgeTint', 'complexIor'):
+            if metal_preset == 'edgeTint':
+                metal_eta = None
+                metal_k = None
+            metal_preset = ''
+
+        sheen_tex = get_texture(m+'.sheen')
+        sheen_color_tex = get_texture(m+'.sheenColor')
+        sheen_roughness_tex = get_texture(m+'.sheenRoughness')
+        subsurface_tex = get_texture(m+'.subsurface')
+        subsurface_color_tex = get_texture(m+'.subsurfaceColor')
+        emission_tex = get_texture(m+'.emission')
+        bump_tex = get_texture(m+'.bump')
+        normal_tex = get_texture(m+'.normal', normal_map=True)
+
+        # interior medium, only exported when it does something
+        medium = None
+        if sum(medium_absorption) + sum(medium_scattering) + sum(medium_emission) > 0:
+            medium = {
+                "absorption": medium_absorption,
+                "scattering": medium_scattering,
+                "emission": medium_emission,
+                "anisotropy": medium_anisotropy,
+                "density": medium_density,
+                "temperature_scale": temperature_scale,
+                "blackbody_intensity": blackbody_intensity,
+            }
+            if medium_grid:
+                medium["grid"] = medium_grid
+            if medium_temperature:
+                medium["temperature"] = medium_temperature
 
         mat = {
             "name": m,
@@ -222,20 +262,61 @@ def get_materials():
             "diffuse_tex": diffuse_tex,
             "diffuse_weight": diffuse_weight,
             "diffuse_weight_tex": diffuse_weight_tex,
+            "diffuse_roughness": diffuse_roughness,
+            "diffuse_roughness_tex": diffuse_roughness_tex,
+            "opacity": opacity,
+            "opacity_tex": opacity_tex,
+            "thin_walled": int(thin_walled),
+            "diffuse_transmission": diffuse_transmission,
+            "diffuse_transmission_tex": diffuse_transmission_tex,
             "specular": specular,
             "specular_tex": specular_tex,
             "specular_weight": specular_weight,
             "specular_weight_tex": specular_weight_tex,
             "roughness": roughness,
             "roughness_tex": roughness_tex,
+            "anisotropy": anisotropy,
+            "anisotropy_tex": anisotropy_tex,
+            "anisotropy_rotation": anisotropy_rotation,
+            "anisotropy_rotation_tex": anisotropy_rotation_tex,
             "ior": ior,
             "metallic": metallic,
             "metallic_tex": metallic_tex,
             "refraction": refraction,
             "refraction_tex": refraction_tex,
+            "transmission": transmission,
+            "transmission_depth": transmission_depth,
+            "clearcoat": clearcoat,
+            "clearcoat_tex": clearcoat_tex,
+            "clearcoat_roughness": clearcoat_roughness,
+            "clearcoat_roughness_tex": clearcoat_roughness_tex,
+            "clearcoat_ior": clearcoat_ior,
+            "clearcoat_normal_tex": clearcoat_normal_tex,
+            "thin_film_thickness": thin_film_thickness,
+            "thin_film_thickness_tex": thin_film_thickness_tex,
+            "thin_film_ior": thin_film_ior,
+            "metal_edge_tint": metal_edge_tint,
+            "metal_edge_tint_tex": metal_edge_tint_tex,
+            "metal_preset": metal_preset,
+            "metal_eta": metal_eta,
+            "metal_k": metal_k,
+            "sheen": sheen,
+            "sheen_tex": sheen_tex,
+            "sheen_color": sheen_color,
+            "sheen_color_tex": sheen_color_tex,
+            "sheen_roughness": sheen_roughness,
+            "sheen_roughness_tex": sheen_roughness_tex,
+            "subsurface": subsurface,
+            "subsurface_tex": subsurface_tex,
+            "subsurface_color": subsurface_color,
+            "subsurface_color_tex": subsurface_color_tex,
+            "subsurface_radius": subsurface_radius,
+            "subsurface_anisotropy": subsurface_anisotropy,
+            "subsurface_mode": subsurface_mode,
+            "volume": int(volume),
+            "medium": medium,
             "emission": emission,
             "emission_tex": emission_tex,
-            "bump": bump,
             "bump_tex": bump_tex,
             "bump_strength": bump_strength,
             "normal_tex": normal_tex,
@@ -244,6 +325,173 @@ def get_materials():
         all_materials.append(mat)
     return all_materials
 
+def get_texture(attr, normal_map=False):
+    # a file with default placement exports as its path, anything else as a shading network
+    sources = cmds.listConnections(attr, source=True, destination=False, plugs=True)
+    if not sources:
+        return ''
+    node = sources[0].split('.')[0]
+    # plain paths load as srgb, or linear in normal slots, other color spaces need the network
+    if (cmds.nodeType(node) == 'file' and not cmds.listConnections(node+'.uvCoord', type='place2dTexture')
+            and (cmds.getAttr(node+'.colorSpace') == 'sRGB') != normal_map):
+        return get_file_path(node)
+    nodes = {}
+    output = get_shading_node(sources[0], nodes)
+    # a triplanar projection feeding a normal slot blends normals rather than colors
+    if normal_map and nodes[output]["type"] == 'triplanar':
+        nodes[output]["type"] = 'triplanar_normal'
+    return {"nodes": nodes, "output": output}
+
+def get_file_path(node):
+    # udim tiling (mari) exports the <UDIM> pattern rather than the first tile
+    if cmds.getAttr(node+'.uvTilingMode') == 3:
+        return cmds.getAttr(node+'.computedFileTextureNamePattern')
+    return cmds.getAttr(node+'.fileTextureName')
+
+def get_shading_input(node, attr, nodes):
+    # connected inputs name the upstream node, unconnected ones export their value
+    sources = cmds.listConnections(node+'.'+attr, source=True, destination=False, plugs=True)
+    if sources:
+        return get_shading_node(sources[0], nodes)
+    value = cmds.getAttr(node+'.'+attr)
+    if isinstance(value, list):
+        return list(value[0])
+    return value
+
+def get_shading_node(plug, nodes):
+    node = plug.split('.')[0]
+    node_type = cmds.nodeType(node)
+    if node_type == 'samplerInfo':
+        nodes[node] = {"type": "facing_ratio"}
+        return node
+    if node in nodes:
+        return node
+    nodes[node] = {}
+
+    if node_type == 'file':
+        shader = {"type": "texture", "path": get_file_path(node), "srgb": cmds.getAttr(node+'.colorSpace') == 'sRGB'}
+        # a filter type of off is a plain nearest lookup, everything else gets mipmapped
+        if cmds.getAttr(node+'.filterType') == 0:
+            shader["filter"] = 'nearest'
+            shader["mipmap"] = 'none'
+        placement = cmds.listConnections(node+'.uvCoord', type='place2dTexture')
+        if placement:
+            shader["repeat"] = list(cmds.getAttr(placement[0]+'.repeatUV')[0])
+            shader["offset"] = list(cmds.getAttr(placement[0]+'.offset')[0])
+            shader["rotate"] = cmds.getAttr(placement[0]+'.rotateUV')
+            if cmds.getAttr(placement[0]+'.mirrorU'):
+                shader["wrap"] = 'mirror'
+            elif not cmds.getAttr(placement[0]+'.wrapU'):
+                shader["wrap"] = 'clamp'
+    elif node_type == 'ramp':
+        stops = []
+        for i in cmds.getAttr(node+'.colorEntryList', multiIndices=True) or []:
+            entry = '%s.colorEntryList[%d]' % (node, i)
+            stops.append({
+                "position": cmds.getAttr(entry+'.position'),
+                "color": get_shading_input(node, 'colorEntryList[%d].color' % i, nodes),
+            })
+        # 0 is a v ramp, 1 a u ramp, other ramp types fall back to v
+        axis = 'u' if cmds.getAttr(node+'.type') == 1 else 'v'
+        interpolation = {0: 'step', 4: 'smooth'}.get(cmds.getAttr(node+'.interpolation'), 'linear')
+        shader = {"type": "ramp", "stops": stops, "axis": axis, "interpolation": interpolation}
+        coord = cmds.listConnections(node+'.'+axis+'Coord', source=True, destination=False, plugs=True)
+        if coord:
+            shader["input"] = get_shading_node(coord[0], nodes)
+    elif node_type == 'blendColors':
+        shader = {
+            "type": "mix",
+            "a": get_shading_input(node, 'color2', nodes),
+            "b": get_shading_input(node, 'color1', nodes),
+            "mask": get_shading_input(node, 'blender', nodes),
+        }
+    elif node_type == 'multiplyDivide':
+        op = {1: 'multiply', 2: 'divide', 3: 'power'}.get(cmds.getAttr(node+'.operation'), 'add')
+        b = get_shading_input(node, 'input2', nodes) if op != 'add' else 0.0
+        shader = {"type": "math", "op": op, "a": get_shading_input(node, 'input1', nodes), "b": b}
+    elif node_type == 'plusMinusAverage':
+        indices = (cmds.getAttr(node+'.input3D', multiIndices=True) or [])[:2]
+        inputs = [get_shading_input(node, 'input3D[%d]' % i, nodes) for i in indices] + [0.0, 0.0]
+        operation = cmds.getAttr(node+'.operation')
+        shader = {"type": "math", "op": 'subtract' if operation == 2 else 'add', "a": inputs[0], "b": inputs[1]}
+        if operation == 3:
+            nodes[node+'_sum'] = shader
+            shader = {"type": "math", "op": 'multiply', "a": node+'_sum', "b": 0.5}
+    elif node_type == 'reverse':
+        shader = {"type": "math", "op": 'invert', "a": get_shading_input(node, 'input', nodes)}
+    elif node_type == 'gammaCorrect':
+        shader = {"type": "color_correct", "input": get_shading_input(node, 'value', nodes), "gamma": cmds.getAttr(node+'.gamma')[0][0]}
+    elif node_type == 'colorCorrect':
+        shader = {
+            "type": "color_correct",
+            "input": get_shading_input(node, 'inColor', nodes),
+            "hue": cmds.getAttr(node+'.hueShift'),
+            "saturation": cmds.getAttr(node+'.satGain'),
+            "gamma": cmds.getAttr(node+'.colGamma')[0][0],
+            "gain": cmds.getAttr(node+'.valGain') * cmds.getAttr(node+'.colGain')[0][0],
+        }
+    elif node_type in ('noise', 'fractal', 'solidFractal'):
+        # maya's fractal settings map onto fbm octaves, solid fractals are placed in world space
+        frequency = cmds.getAttr(node+'.frequency') if node_type == 'noise' else 1.0
+        shader = {
+            "type": "fbm",
+            "frequency": frequency,
+            "octaves": cmds.getAttr(node+'.levelMax' if node_type == 'fractal' else node+'.depthMax'),
+            "gain": cmds.getAttr(node+'.ratio'),
+            "lacunarity": cmds.getAttr(node+'.frequencyRatio'),
+            "space": 'world' if node_type == 'solidFractal' else 'uv',
+        }
+    elif node_type == 'checker':
+        shader = {
+            "type": "checker",
+            "frequency": 2.0,
+            "a": get_shading_input(node, 'color1', nodes),
+            "b": get_shading_input(node, 'color2', nodes),
+        }
+    elif node_type == 'projection' and cmds.getAttr(node+'.projType') == 6:
+        shader = {"type": "triplanar", "input": get_shading_input(node, 'image', nodes), "space": 'world'}
+    else:
+        # unsupported nodes bake to the value maya currently reports for the plug
+        cmds.warning('krusty: %s nodes are not supported, exporting %s as a constant' % (node_type, plug))
+        value = cmds.getAttr(plug)
+        shader = {"type": "constant", "value": list(value[0]) if isinstance(value, list) else value}
+    nodes[node] = shader
+    return node
+
+def get_composite_materials():
+    # mixes and layers name the materials they combine, children have to come first
+    composites = []
+    for m in cmds.ls(type='krustyMix'):
+        composites.append({
+            "name": m,
+            "type": "mix",
+            "a": (cmds.listConnections(m+'.materialA') or [''])[0],
+            "b": (cmds.listConnections(m+'.materialB') or [''])[0],
+            "mask": cmds.getAttr(m+'.mask')[0],
+            "mask_tex": get_texture(m+'.mask'),
+        })
+    for m in cmds.ls(type='krustyLayer'):
+        composites.append({
+            "name": m,
+            "type": "layer",
+            "coat": (cmds.listConnections(m+'.coat') or [''])[0],
+            "base": (cmds.listConnections(m+'.base') or [''])[0],
+            "weight": cmds.getAttr(m+'.weight')[0],
+            "weight_tex": get_texture(m+'.weight'),
+        })
+
+    # order so every composite follows the composites it references
+    ordered = []
+    pending = list(composites)
+    while pending:
+        names = [c["name"] for c in pending]
+        ready = [c for c in pending if not any(c.get(k) in names for k in ("a", "b", "coat", "base"))]
+        if not ready:
+            raise RuntimeError("Cyclic krusty mix or layer network.")
+        ordered.extend(ready)
+        pending = [c for c in pending if c not in ready]
+    return ordered
+
 def get_quad_lights():
     all_lights = []
     lights = cmds.ls('*.krrustyLight', o=True)
@@ -283,6 +531,7 @@ def get_dir_lights():
 spheres = get_spheres()
 meshes = get_meshes()
 materials = get_materials()
+composite_materials = get_composite_materials()
 quad_lights = get_quad_lights()
 dir_lights = get_dir_lights()
 lights = {"quad": quad_lights, "dir": dir_lights}
@@ -299,11 +548,15 @@ width = 1920
 height = int(width / aspect_ratio)
 aperature = cmds.getAttr(cam + '.aperature') / 10
 
+# global fog, e.g. {"absorption": [0.01, 0.01, 0.01], "scattering": [0.05, 0.05, 0.05], "anisotropy": 0.3}
+fog = None
+
 scene_data = {
     "scene": {
         "meshes": meshes,
         "spheres": spheres,
         "materials": materials,
+        "composite_materials": composite_materials,
         "lights": lights,
         "mesh_count": len(meshes),
         "sphere_count": len(spheres),
@@ -318,6 +571,9 @@ scene_data = {
         "aspect_ratio": aspect_ratio,
         "spp": 1024,
         "depth": 32,
+        "denoise": 0,
+        "texture_cache_mb": 2048,
+        "fog": fog,
         "aperature": aperature,
         "fov": fov,
         "camera_origin": [
@@ -0,0 +1,170 @@
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::grid::DensityGrid;
+use crate::pdf::HenyeyGreensteinPdf;
+use crate::utility::random_float;
+use std::sync::Arc;
+
+
+pub enum MediumEvent {
+    Scatter { point: Vec3, beta: Color, emitted: Color },
+    Absorb { emitted: Color },
+    Pass { beta: Color, emitted: Color },
+}
+
+// absorption, scattering and emission are coefficients per unit length, scaled by density,
+// a temperature grid adds blackbody emission weighted by absorption
+#[derive(Debug, Clone)]
+pub struct Medium {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub emission: Color,
+    pub anisotropy: f64,
+    pub density: f64,
+    pub grid: Option<Arc<DensityGrid>>,
+    pub temperature: Option<Arc<DensityGrid>>,
+    pub temperature_scale: f64,
+    pub blackbody_intensity: f64,
+}
+
+impl Medium {
+    pub fn new(absorption: Color, scattering: Color, emission: Color, anisotropy: f64, density: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+            emission,
+            anisotropy,
+            density,
+            grid: None,
+            temperature: None,
+            temperature_scale: 1.0,
+            blackbody_intensity: 1.0,
+        }
+    }
+
+    pub fn phase(&self, direction: Vec3) -> HenyeyGreensteinPdf {
+        HenyeyGreensteinPdf::new(direction, self.anisotropy)
+    }
+
+    fn density_at(&self, p: Vec3) -> f64 {
+        self.density * self.grid.as_ref().map(|g| g.lookup(p)).unwrap_or(1.0)
+    }
+
+    fn emission_at(&self, p: Vec3, density: f64, sigma_a: Color) -> Color {
+        let mut emission = self.emission * density;
+        if let Some(temperature) = &self.temperature {
+            let kelvin = temperature.lookup(p) * self.temperature_scale;
+            emission = emission + sigma_a * Color::blackbody(kelvin) * self.blackbody_intensity;
+        }
+        emission
+    }
+
+    // spans of constant majorant along a unit direction, the grid's coarse cells or one span
+    fn segments(&self, origin: Vec3, direction: Vec3, distance_max: f64) -> Vec<(f64, f64, f64)> {
+        let sigma_t = self.absorption + self.scattering;
+        let sigma_max = sigma_t.r.max(sigma_t.g).max(sigma_t.b) * self.density;
+        let Some(grid) = &self.grid else {
+            return vec![(0.0, distance_max, sigma_max)]
+        };
+
+        // clip to the grid bounds first
+        let (mut near, mut far) = (0.0, distance_max);
+        for (o, d, min, max) in [
+            (origin.x, direction.x, grid.min.x, grid.max.x),
+            (origin.y, direction.y, grid.min.y, grid.max.y),
+            (origin.z, direction.z, grid.min.z, grid.max.z),
+        ] {
+            let inv = 1.0 / d;
+            let (t0, t1) = ((min - o) * inv, (max - o) * inv);
+            near = f64::max(near, f64::min(t0, t1));
+            far = f64::min(far, f64::max(t0, t1));
+        }
+        if near >= far {
+            return Vec::new()
+        }
+        grid.majorant_segments(origin, direction, near, far)
+            .into_iter()
+            .map(|(t0, t1, value)| (t0, t1, value * sigma_max))
+            .collect()
+    }
+
+    // delta tracking up to the next surface, null collisions carry chromatic weights
+    pub fn sample(&self, r: &Ray, t_max: f64) -> MediumEvent {
+        let length = r.direction.length();
+        let direction = r.direction / length;
+        let mut beta = Color::white();
+        let mut emitted = Color::black();
+
+        for (start, end, majorant) in self.segments(r.origin, direction, t_max * length) {
+            if majorant <= 0.0 {
+                continue
+            }
+            let mut distance = start;
+            loop {
+                distance -= (1.0 - random_float()).ln() / majorant;
+                if distance >= end {
+                    break
+                }
+                let point = r.origin + direction * distance;
+                let density = self.density_at(point);
+                let sigma_a = self.absorption * density;
+                let sigma_s = self.scattering * density;
+                let emission = self.emission_at(point, density, sigma_a) / majorant;
+                emitted = emitted + Color::new(beta.r * emission.r, beta.g * emission.g, beta.b * emission.b, 0.0);
+
+                let absorb_prob = sigma_a.sum() / (3.0 * majorant);
+                let scatter_prob = sigma_s.sum() / (3.0 * majorant);
+                let null_prob = f64::max(1.0 - absorb_prob - scatter_prob, 0.0);
+                let roll = random_float();
+                if roll < absorb_prob {
+                    return MediumEvent::Absorb { emitted }
+                } else if roll < absorb_prob + scatter_prob {
+                    beta = beta * sigma_s / (majorant * scatter_prob);
+                    beta.a = 1.0;
+                    return MediumEvent::Scatter { point, beta, emitted }
+                }
+                let sigma_n = Color::white() * majorant - sigma_a - sigma_s;
+                beta = beta * sigma_n / (majorant * null_prob);
+                beta.a = 1.0;
+                if beta.sum() <= 0.0 {
+                    return MediumEvent::Absorb { emitted }
+                }
+            }
+        }
+        MediumEvent::Pass { beta, emitted }
+    }
+
+    // ratio tracking estimate of transmittance up to t_max
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> Color {
+        let length = r.direction.length();
+        let direction = r.direction / length;
+        let mut transmittance = Color::white();
+
+        for (start, end, majorant) in self.segments(r.origin, direction, t_max * length) {
+            if majorant <= 0.0 {
+                continue
+            }
+            let mut distance = start;
+            loop {
+                distance -= (1.0 - random_float()).ln() / majorant;
+                if distance >= end {
+                    break
+                }
+                let sigma_t = (self.absorption + self.scattering) * self.density_at(r.origin + direction * distance);
+                transmittance = transmittance * (Color::white() - sigma_t / majorant);
+                transmittance.a = 1.0;
+
+                // roulette once little light is left
+                let remaining = transmittance.r.max(transmittance.g).max(transmittance.b);
+                if remaining < 0.1 {
+                    if random_float() > remaining {
+                        return Color::black()
+                    }
+                    transmittance = transmittance / remaining;
+                }
+            }
+        }
+        transmittance
+    }
+}
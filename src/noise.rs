@@ -0,0 +1,165 @@
+use crate::vec3::Vec3;
+use std::sync::OnceLock;
+
+
+// perlin's improved noise permutation, shuffled once from a fixed seed so renders repeat
+static PERMUTATION: OnceLock<[u8; 512]> = OnceLock::new();
+
+fn permutation() -> &'static [u8; 512] {
+    PERMUTATION.get_or_init(|| {
+        let mut p: Vec<u8> = (0..=255).collect();
+        let mut state: u32 = 0x2545f491;
+        for i in (1..256).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            p.swap(i, state as usize % (i + 1));
+        }
+        let mut table = [0; 512];
+        for i in 0..512 {
+            table[i] = p[i & 255];
+        }
+        table
+    })
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 {x} else {y};
+    let v = if h < 4 {y} else if h == 12 || h == 14 {x} else {z};
+    (if h & 1 == 0 {u} else {-u}) + (if h & 2 == 0 {v} else {-v})
+}
+
+// gradient noise in roughly [-1, 1]
+pub fn perlin(p: Vec3) -> f64 {
+    let perm = permutation();
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (xi, yi, zi) = ((fx as i64 & 255) as usize, (fy as i64 & 255) as usize, (fz as i64 & 255) as usize);
+    let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = perm[xi] as usize + yi;
+    let (aa, ab) = (perm[a] as usize + zi, perm[a + 1] as usize + zi);
+    let b = perm[xi + 1] as usize + yi;
+    let (ba, bb) = (perm[b] as usize + zi, perm[b + 1] as usize + zi);
+
+    lerp(w,
+        lerp(v,
+            lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1.0, y, z)),
+            lerp(u, grad(perm[ab], x, y - 1.0, z), grad(perm[bb], x - 1.0, y - 1.0, z))),
+        lerp(v,
+            lerp(u, grad(perm[aa + 1], x, y, z - 1.0), grad(perm[ba + 1], x - 1.0, y, z - 1.0)),
+            lerp(u, grad(perm[ab + 1], x, y - 1.0, z - 1.0), grad(perm[bb + 1], x - 1.0, y - 1.0, z - 1.0))))
+}
+
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+// 3d simplex noise after gustavson, roughly [-1, 1]
+pub fn simplex(p: Vec3) -> f64 {
+    const F3: f64 = 1.0 / 3.0;
+    const G3: f64 = 1.0 / 6.0;
+    let perm = permutation();
+
+    // skew into the simplex grid and find which of the six tetrahedra we're in
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = ((p.x + s).floor(), (p.y + s).floor(), (p.z + s).floor());
+    let t = (i + j + k) * G3;
+    let d0 = [p.x - (i - t), p.y - (j - t), p.z - (k - t)];
+    let (o1, o2) = if d0[0] >= d0[1] {
+        if d0[1] >= d0[2] {([1, 0, 0], [1, 1, 0])}
+        else if d0[0] >= d0[2] {([1, 0, 0], [1, 0, 1])}
+        else {([0, 0, 1], [1, 0, 1])}
+    } else if d0[1] < d0[2] {([0, 0, 1], [0, 1, 1])}
+    else if d0[0] < d0[2] {([0, 1, 0], [0, 1, 1])}
+    else {([0, 1, 0], [1, 1, 0])};
+
+    let (ii, jj, kk) = ((i as i64 & 255) as usize, (j as i64 & 255) as usize, (k as i64 & 255) as usize);
+    let mut n = 0.0;
+    for (corner, offset) in [([0, 0, 0], 0.0), (o1, G3), (o2, 2.0 * G3), ([1, 1, 1], 3.0 * G3)] {
+        let d = [
+            d0[0] - corner[0] as f64 + offset,
+            d0[1] - corner[1] as f64 + offset,
+            d0[2] - corner[2] as f64 + offset,
+        ];
+        let falloff = 0.6 - d[0] * d[0] - d[1] * d[1] - d[2] * d[2];
+        if falloff > 0.0 {
+            let g = GRAD3[perm[ii + corner[0] + perm[jj + corner[1] + perm[kk + corner[2]] as usize] as usize] as usize % 12];
+            n += falloff.powi(4) * (g[0] * d[0] + g[1] * d[1] + g[2] * d[2]);
+        }
+    }
+    32.0 * n
+}
+
+// fractal sums normalized by the total amplitude, turbulence folds every octave with abs
+pub fn fbm(p: Vec3, basis: fn(Vec3) -> f64, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+    octave_sum(p, octaves, lacunarity, gain, basis)
+}
+
+pub fn turbulence(p: Vec3, basis: fn(Vec3) -> f64, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+    octave_sum(p, octaves, lacunarity, gain, |q| basis(q).abs())
+}
+
+fn octave_sum(p: Vec3, octaves: u32, lacunarity: f64, gain: f64, f: impl Fn(Vec3) -> f64) -> f64 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * f(p * frequency);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    sum / total
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841) ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+// [0, 1) from a hash and a stream index
+pub fn hash_float(h: u32, stream: u32) -> f64 {
+    hash(h as i64, stream as i64, 0x5bd1e995) as f64 / 4294967296.0
+}
+
+// distances to the nearest and second nearest jittered feature points, and the nearest point's cell hash.
+// planar keeps every feature point on z = 0 for uv lookups
+pub fn worley(p: Vec3, jitter: f64, planar: bool) -> (f64, f64, u32) {
+    let (cx, cy, cz) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let z_range = if planar {0..=0} else {-1..=1};
+    let (mut f1, mut f2, mut cell) = (f64::INFINITY, f64::INFINITY, 0);
+    for dz in z_range {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y, z) = (cx + dx, cy + dy, if planar {0} else {cz + dz});
+                let h = hash(x, y, z);
+                let feature = Vec3::new(
+                    x as f64 + 0.5 + jitter * (hash_float(h, 0) - 0.5),
+                    y as f64 + 0.5 + jitter * (hash_float(h, 1) - 0.5),
+                    if planar {0.0} else {z as f64 + 0.5 + jitter * (hash_float(h, 2) - 0.5)},
+                );
+                let distance = (feature - p).length();
+                if distance < f1 {
+                    (f2, f1, cell) = (f1, distance, h);
+                } else if distance < f2 {
+                    f2 = distance;
+                }
+            }
+        }
+    }
+    (f1, f2, cell)
+}
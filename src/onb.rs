@@ -31,6 +31,11 @@ impl Onb {
         a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
     }
 
+    // inverse of local, world space to this basis
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u()), a.dot(&self.v()), a.dot(&self.w()))
+    }
+
     pub fn build_from_w(&mut self, n: Vec3) {
         self.axis[2] = n.normalize();
         let a = if self.w().x().abs() > 0.9 {
@@ -41,4 +46,15 @@ impl Onb {
         self.axis[1] = Vec3::cross(&self.w(), &a).normalize();
         self.axis[0] = Vec3::cross(&self.w(), &self.v());
     }
+
+    // keeps u aligned with a surface tangent so anisotropy follows the uv layout
+    pub fn build_from_w_tangent(&mut self, n: Vec3, tangent: Vec3) {
+        self.axis[2] = n.normalize();
+        let u = tangent - self.w() * self.w().dot(&tangent);
+        if u.length_squared() < 1e-12 {
+            return self.build_from_w(n)
+        }
+        self.axis[0] = u.normalize();
+        self.axis[1] = Vec3::cross(&self.w(), &self.u());
+    }
 }
\ No newline at end of file
@@ -2,9 +2,7 @@ use crate::vec3::Vec3;
 use crate::onb::Onb;
 use crate::lights::QuadLight;
 use crate::hit::Object;
-use crate::utility::{random_float, INF};
-use crate::hit::Hittable;
-use crate::ray::Ray;
+use crate::utility::random_float;
 use std::sync::{Arc, Mutex, RwLock};
 
 
@@ -36,91 +34,120 @@ impl Pdf for CosinePdf {
     }
 }
 
+// henyey-greenstein phase function around the incoming direction, g > 0 scatters forward
+pub struct HenyeyGreensteinPdf {
+    pub uvw: Onb,
+    pub g: f64,
+}
+
+impl HenyeyGreensteinPdf {
+    pub fn new(direction: Vec3, g: f64) -> Self {
+        let mut onb = Onb::new();
+        onb.build_from_w(direction);
+        Self { uvw: onb, g: g.clamp(-0.99, 0.99) }
+    }
+}
+
+impl Pdf for HenyeyGreensteinPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = Vec3::dot(&direction.normalize(), &self.uvw.w());
+        let denom = 1.0 + self.g * self.g - 2.0 * self.g * cosine;
+        (1.0 - self.g * self.g) / (4.0 * std::f64::consts::PI * denom * denom.sqrt())
+    }
+
+    fn generate(&self) -> Vec3 {
+        let (u1, u2) = (random_float(), random_float());
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+        self.uvw.local(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+}
+
 pub struct LightPdf {
     pub lights: Arc<Vec<Object>>,
     point: Vec3,
-    normal: Vec3
 }
 
 impl LightPdf{
-    pub fn new(lights: Arc<Vec<Object>>, point: Vec3, normal: Vec3) -> LightPdf {
+    pub fn new(lights: Arc<Vec<Object>>, point: Vec3) -> LightPdf {
         LightPdf {
             lights,
             point,
-            normal
         }
     }
+
+    // lights are picked proportionally to their approximate solid angle
+    fn selection_weight(&self, quad_light: &QuadLight) -> f64 {
+        let distance_squared = (quad_light.position - self.point).length_squared();
+        quad_light.area / distance_squared
+    }
+
+    fn selection_sum(&self) -> f64 {
+        let mut sum_pdf = 0.0;
+        for light in self.lights.iter() {
+            if let Object::QuadLight(quad_light) = light {
+                sum_pdf += self.selection_weight(quad_light);
+            }
+        }
+        sum_pdf
+    }
 }
 
 impl Pdf for LightPdf {
+    // solid angle density of generate(), summed over every light the direction hits
     fn value(&self, direction: &Vec3) -> f64 {
-        let ray = Ray::new(self.point, *direction, 0.0);
-        for light in self.lights.iter(){
-            if let (true, Some(hit_rec)) = light.hit(&ray, 0.0001, INF) {
-                let distance_squared = (hit_rec.point - self.point).length_squared();
-                let cosine = self.normal.dot(&direction).max(0.0);
-                let mut area = 0.0;
-                let mut intensity = 1.0;
-                match light {
-                    Object::QuadLight(quad_light) => {
-                        area = quad_light.area;
-                        intensity = quad_light.intensity;
-                    }
-                    _ => {}
-                }
-                return distance_squared / (cosine * area);            
+        let sum_pdf = self.selection_sum();
+        let mut pdf = 0.0;
+        for light in self.lights.iter() {
+            if let Object::QuadLight(quad_light) = light {
+                let select = self.selection_weight(quad_light) / sum_pdf;
+                pdf += select * quad_light.pdf_value(&self.point, direction);
             }
         }
-    0.0
+        pdf
     }
 
     fn generate(&self) -> Vec3 {
-        let mut to_light = Vec3::black();
-        let mut on_light = Vec3::black();
-        let mut distance_squared = 0.0;
-        let mut light_cosine = 0.0;
-        let mut pdf_val = 0.0;
-        let mut sum_pdf = 0.0;
-        for (i, light) in self.lights.iter().enumerate() {
-            match light {
-                Object::QuadLight(quad_light) => {
-                    let distance_squared = (quad_light.position - self.point).length_squared();
-                    sum_pdf += quad_light.area / distance_squared;
-                }
-                _ => {}
-            }
-        }
-
+        let sum_pdf = self.selection_sum();
+        let roll = random_float() * sum_pdf;
+        let mut cdf = 0.0;
         let mut chosen_light = None;
-        for (i, light) in self.lights.iter().enumerate() {
-            match light {
-                Object::QuadLight(quad_light) => {
-                    let distance_squared = (quad_light.position - self.point).length_squared();
-                    let pdf = quad_light.area / distance_squared;
-                    if chosen_light.is_none() && random_float() < pdf / sum_pdf {
-                        chosen_light = Some(quad_light);
-                    }
+        for light in self.lights.iter() {
+            if let Object::QuadLight(quad_light) = light {
+                cdf += self.selection_weight(quad_light);
+                chosen_light = Some(quad_light);
+                if roll < cdf {
+                    break;
                 }
-                _ => {}
             }
         }
 
-        // generate scatter direction based on pdf
-        if let Some(quad_light) = chosen_light {
-            // Generate a random point on the selected light
-            let (s, t) = (random_float(), random_float());
-            on_light = quad_light.position
-                + quad_light.x_axis * (s - 0.5) * quad_light.width
-                + quad_light.y_axis * (t - 0.5) * quad_light.height;  
-
-            // Compute the direction to the random point on the light
-            to_light = on_light - self.point;
-            distance_squared = to_light.length_squared();
-            
+        // uniformly sample a point on the chosen light
+        match chosen_light {
+            Some(quad_light) => {
+                let (s, t) = (random_float(), random_float());
+                let on_light = quad_light.position
+                    + quad_light.x_axis * (s - 0.5) * quad_light.width
+                    + quad_light.y_axis * (t - 0.5) * quad_light.height;  
+                (on_light - self.point).normalize()
+            }
+            None => Vec3::zeros()
         }
-        to_light.normalize()
     }
 }
 
-
-
+// one-sample multiple importance sampling weight, pdfs are pre-scaled by their strategy probability
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let (f2, g2) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f2 + g2 <= 0.0 {
+        return 0.0
+    }
+    f2 / (f2 + g2)
+}
@@ -1,15 +1,23 @@
 use crate::vec3::Vec3;
 
+// the cone is the ray's footprint for texture filtering, its width at the origin
+// and how fast it grows per unit of distance
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: f64,
+    pub cone_width: f64,
+    pub cone_spread: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3, time: f64) -> Ray {
-        Ray {origin, direction, time}
+        Ray {origin, direction, time, cone_width: 0.0, cone_spread: 0.0}
+    }
+
+    pub fn cone_width_at(&self, t: f64) -> f64 {
+        self.cone_width + self.cone_spread * t * self.direction.length()
     }
 
     pub fn at(&self, t: f64) -> Vec3 {
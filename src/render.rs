@@ -1,112 +1,231 @@
-use crate::bvh::Bvh;
 use crate::camera::Camera;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use crate::color::Color;
-use crate::aabb::Aabb;
-use crate::buffers::{Lobes, FrameBuffers};
-use image::{ImageBuffer, Rgb, Rgba, RgbImage, RgbaImage, Rgb32FImage, Rgba32FImage};
-use std::io::Write;
-use std::{env, fs, thread};
-use std::sync::{Arc, Mutex, RwLock};
+use crate::buffers::Lobes;
+use std::sync::Arc;
 use crate::utility::{random_float, INF};
-use crate::hit::{HitRecord, HittableList, Object, Hittable};
+use crate::hit::{HitRecord, Object, Hittable};
 use std::f64::consts::PI;
 use crate::texture::TextureMap;
+use crate::shader::ShadingPoint;
 use crate::lights::DirectionalLight;
-use crate::material::{Emits, Light, Material, Principle, Scatterable};
-
-
-pub fn ray_color(   
-    r: &Ray, 
-    world: &Object, 
-    quad_lights: &Arc<Vec<Object>>, 
-    dir_lights: &Arc<Vec<DirectionalLight>>, 
-    depth: u32, 
-    max_depth: u32, 
-    progressive: bool, 
-    skydome: &Option<Arc<TextureMap>>,
-    hide_skydome: bool
-    ) -> Lobes {
-
-    if depth <= 0 {
+use crate::material::Scatterable;
+use crate::medium::{Medium, MediumEvent};
+use crate::pdf::{Pdf, LightPdf, power_heuristic};
+
+
+// scene state shared by every ray of a sample, the medium and depth change per bounce
+pub struct RenderContext<'a> {
+    pub world: &'a Object,
+    pub quad_lights: &'a Arc<Vec<Object>>,
+    pub dir_lights: &'a Arc<Vec<DirectionalLight>>,
+    pub max_depth: u32,
+    pub skydome: &'a Option<Arc<TextureMap>>,
+    pub hide_skydome: bool,
+    pub fog: &'a Option<Arc<Medium>>,
+}
+
+pub fn ray_color(r: &Ray, ctx: &RenderContext, depth: u32, medium: &Option<Arc<Medium>>) -> Lobes {
+    if depth == 0 {
         return Lobes::empty();
     }
+    let (_, hit) = ctx.world.hit(r, 0.0001, INF);
+
+    // travel through the current medium up to the next surface
+    let Some(current) = medium else {
+        return shade(r, hit, ctx, depth, medium)
+    };
+    let t_max = hit.as_ref().map(|h| h.t).unwrap_or(INF);
+    match current.sample(r, t_max) {
+        MediumEvent::Absorb { emitted } => {
+            let mut color = Lobes::empty();
+            color.rgba = emitted;
+            color.rgba.a = coverage(depth, ctx.max_depth, 0.0);
+            color
+        },
+        MediumEvent::Scatter { point, beta, emitted } => {
+            let mut color = in_scatter(r, point, current, ctx, depth, medium);
+            color.rgba = emitted + beta * color.rgba;
+            color.rgba.a = coverage(depth, ctx.max_depth, 0.0);
+            color
+        },
+        MediumEvent::Pass { beta, emitted } => {
+            let mut color = shade(r, hit, ctx, depth, medium);
+            color.rgba = emitted + beta * color.rgba;
+            color.diffuse = beta * color.diffuse;
+            color.specular = beta * color.specular;
+            color.clearcoat = beta * color.clearcoat;
+            color
+        },
+    }
+}
+
+// phase function sampled against the quad lights, plus directional lights seen through the media
+fn in_scatter(r: &Ray, point: Vec3, current: &Medium, ctx: &RenderContext, depth: u32, medium: &Option<Arc<Medium>>) -> Lobes {
+    let phase = current.phase(r.direction);
+    let light_pdf = LightPdf::new(ctx.quad_lights.clone(), point);
+    let light_prob = if ctx.quad_lights.is_empty() {0.0} else {0.5};
+
+    // one-sample mis between light and phase sampling
+    let direct = random_float() < light_prob;
+    let direction = if direct {light_pdf.generate()} else {phase.generate()};
+    let phase_value = phase.value(&direction);
+    let phase_pdf = phase_value * (1.0 - light_prob);
+    let light_pdf_val = light_pdf.value(&direction) * light_prob;
+    let (chosen_pdf, other_pdf) = if direct {(light_pdf_val, phase_pdf)} else {(phase_pdf, light_pdf_val)};
+    let weight = if chosen_pdf > 0.0 {power_heuristic(chosen_pdf, other_pdf) / chosen_pdf} else {0.0};
+
+    let mut scattered = Ray::new(point, direction, r.time);
+    scattered.cone_width = r.cone_width + r.cone_spread * (point - r.origin).length();
+    scattered.cone_spread = r.cone_spread;
+    let sample = ray_color(&scattered, ctx, depth - 1, medium);
+    let mut rgba = sample.rgba * (phase_value * weight);
+
+    for dir_light in ctx.dir_lights.iter() {
+        let (to_light, radiance) = dir_light.sample();
+        let transmittance = shadow_transmittance(point, to_light, r.time, ctx.world, medium, ctx.fog);
+        rgba = rgba + radiance * transmittance * phase.value(&to_light);
+    }
+    rgba.a = 1.0;
+
+    let mut color = Lobes::empty();
+    color.rgba = rgba;
+    color
+}
+
+// transmittance towards a light. medium boundaries are crossed, transmissive surfaces pass
+// what goes straight through them and switch into their interior medium, the rest block
+fn shadow_transmittance(point: Vec3, direction: Vec3, time: f64, world: &Object, medium: &Option<Arc<Medium>>, fog: &Option<Arc<Medium>>) -> Color {
+    let mut transmittance = Color::white();
+    let mut ray = Ray::new(point, direction, time);
+    let mut current = medium.clone();
+    for _ in 0..16 {
+        let (_, hit) = world.hit(&ray, 0.0001, INF);
+        if let Some(m) = &current {
+            transmittance = transmittance * m.transmittance(&ray, hit.as_ref().map(|h| h.t).unwrap_or(INF));
+        }
+        match hit {
+            None => return transmittance,
+            Some(hit_rec) => {
+                let sp = ShadingPoint::new(&hit_rec, &ray);
+                transmittance = transmittance * hit_rec.material.shadow_transmittance(&sp, &hit_rec, &ray);
+                if transmittance.r.max(transmittance.g).max(transmittance.b) <= 0.0 {
+                    return Color::black()
+                }
+                if let Some(interior) = hit_rec.material.interior_medium() {
+                    current = if hit_rec.front_face {Some(interior)} else {fog.clone()};
+                }
+                ray = Ray::new(hit_rec.point, direction, time);
+            },
+        }
+    }
+    Color::black()
+}
+
+fn shade(r: &Ray, hit: Option<HitRecord>, ctx: &RenderContext, depth: u32, medium: &Option<Arc<Medium>>) -> Lobes {
+
+    if let Some(hit_rec) = hit {
+        let sp = ShadingPoint::new(&hit_rec, r);
+
+        // first-hit features for the denoiser
+        let (albedo_aov, normal_aov) = if depth == ctx.max_depth {
+            let n = hit_rec.normal;
+            (hit_rec.material.albedo(&sp), Color::new(n.x, n.y, n.z, 1.0))
+        } else {
+            (Color::black(), Color::black())
+        };
+
+        // mixes pick the material that shades this sample
+        let material = hit_rec.material.select(&sp);
+        if let Some((mut ray, albedo, emission, lobe)) = material.scatter(r, &hit_rec, ctx.quad_lights, ctx.world) {
+            // the cone carries on from its width at the hit, lobes don't widen it
+            ray.cone_width = r.cone_width_at(hit_rec.t);
+            ray.cone_spread = r.cone_spread;
+
+            // crossing a closed mesh swaps between its interior medium and the fog outside
+            let next_medium = match material.interior_medium() {
+                Some(interior) if ray.direction.dot(&hit_rec.normal) < 0.0 => {
+                    if hit_rec.front_face {Some(interior)} else {ctx.fog.clone()}
+                },
+                _ => medium.clone(),
+            };
 
-    if let (true, Some(hit_rec)) = world.hit(&r, 0.0001, INF) {
-        if let Some((ray, albedo, emission, lobe)) = hit_rec.material.scatter(&r, &hit_rec, quad_lights) {
             // sample scene
-            let sample = ray_color(&ray, &world, &quad_lights, &dir_lights, depth - 1, max_depth, progressive, skydome, hide_skydome);
+            let sample = ray_color(&ray, ctx, depth - 1, &next_medium);
             let emit = if hit_rec.front_face {emission} else {Color::black()};
             let composite = emit + albedo * sample.rgba;
 
             // sort lobes
             let mut color = Lobes::empty();
             color.rgba = composite;
-            color.diffuse = if lobe == "diffuse" {composite} else {Color::black()};
+            color.rgba.a = coverage(depth, ctx.max_depth, composite.a);
+            color.diffuse = if lobe == "diffuse" || lobe == "subsurface" || lobe == "translucent" {composite} else {Color::black()};
             color.specular = 
             if lobe == "specular" {composite}
             else {Color::black()};
+            color.clearcoat = if lobe == "clearcoat" {composite} else {Color::black()};
             color.emission = emission;
+            color.albedo = albedo_aov;
+            color.normal = normal_aov;
 
             // material properties
             let mut diffuse_weight = 0.0;
             let mut specular_weight = 0.0;
             let mut roughness = 0.0;
             
-            if let Material::Principle(principle) = &*hit_rec.material {
+            if let Some(principle) = material.surface() {
                 diffuse_weight = principle.diffuse_weight;
-                if let Some(dwt) = &principle.diffuse_weight_texture {
-                    diffuse_weight = principle.diffuse_weight_texture
-                        .as_ref()
-                        .map(|t| t.sample(hit_rec.uv.x, hit_rec.uv.y))
-                        .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
-                } 
+                if let Some(t) = &principle.diffuse_weight_texture {
+                    diffuse_weight = t.evaluate(&sp).r;
+                }
                 specular_weight = principle.specular_weight;
-                if let Some(rt) = &principle.specular_weight_texture {
-                    specular_weight = principle.specular_weight_texture
-                        .as_ref()
-                        .map(|t| t.sample(hit_rec.uv.x, hit_rec.uv.y))
-                        .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
-                } 
+                if let Some(t) = &principle.specular_weight_texture {
+                    specular_weight = t.evaluate(&sp).r;
+                }
                 roughness = principle.roughness;
-                if let Some(rt) = &principle.roughness_texture {
-                    roughness = principle.roughness_texture
-                        .as_ref()
-                        .map(|t| t.sample(hit_rec.uv.x, hit_rec.uv.y))
-                        .unwrap_or_else(|| Color::new(0.0, 1.0, 1.0, 1.0)).r;
-                } 
+                if let Some(t) = &principle.roughness_texture {
+                    roughness = t.evaluate(&sp).r;
+                }
                 roughness = (1.0 - roughness).powf(4.0) * 1000.0 + 3.5;
 
                 // directional lights
                 let view_dir = -(r.direction).normalize();
-                for dir_light in dir_lights.iter() {
-                    let contrib = dir_light.irradiance(hit_rec.normal, view_dir, roughness, &lobe);
-                    if !dir_light.shadow(&hit_rec.point, &world){
-                        if lobe == "diffuse" {
-                            color.rgba = color.rgba + (albedo * contrib * diffuse_weight);
-                            color.diffuse = color.diffuse + (albedo * contrib * diffuse_weight);
-                        } else if lobe == "specular" {
-                            color.rgba = color.rgba + (contrib * specular_weight);
-                            color.specular = color.specular + (contrib * specular_weight);
-                        }                        
-                    }   
-                }                   
+                for dir_light in ctx.dir_lights.iter() {
+                    // the same jittered direction is lit and shadowed, so soft shadows line up with the shading
+                    let (to_light, _) = dir_light.sample();
+                    let irradiance = dir_light.irradiance(to_light, hit_rec.normal, view_dir, roughness, &lobe);
+                    if irradiance.sum() <= 0.0 {
+                        continue
+                    }
+                    let contrib = irradiance * shadow_transmittance(hit_rec.point, to_light, r.time, ctx.world, medium, ctx.fog);
+                    if lobe == "diffuse" || lobe == "translucent" {
+                        color.rgba = color.rgba + (albedo * contrib * diffuse_weight);
+                        color.diffuse = color.diffuse + (albedo * contrib * diffuse_weight);
+                    } else if lobe == "specular" {
+                        color.rgba = color.rgba + (contrib * specular_weight);
+                        color.specular = color.specular + (contrib * specular_weight);
+                    }
+                }
             }
-            
-            // cull and clip
+
+            // cull and clip, features are kept so the denoiser still sees the surface
+            let mut culled = Lobes::empty();
+            culled.rgba.a = coverage(depth, ctx.max_depth, 0.0);
+            culled.albedo = albedo_aov;
+            culled.normal = normal_aov;
             if color.rgba.sum() < 0.001 && color.emission.sum() < 0.001 {
-                return Lobes::empty()
+                return culled
             } else if color.rgba.max() > 80.0 && color.emission.sum() < 0.001 {
-                return Lobes::empty()
+                return culled
             } else if color.rgba.has_nan() {
-                return Lobes::empty()
+                return culled
             } else {
                 return color
             }                 
         }
     }
-    match skydome {
+    match ctx.skydome {
         Some(ref sky) => {
             let unit_direction = Vec3::normalize(&r.direction);
             let rotation_degrees: f64 = 60.0;// crab rotation 60.0
@@ -117,14 +236,17 @@ pub fn ray_color(
             let v = 1.0 - (theta + PI / 2.0) / PI;        
             let mut sky_color = sky.sample(u as f32, v as f32);
 
-            if depth == max_depth && hide_skydome {
+            if depth == ctx.max_depth && ctx.hide_skydome {
                 return Lobes::empty()
             } else {
                 return Lobes {
                     rgba: sky_color,
                     diffuse: Color::black(),
                     specular: Color::black(), 
+                    clearcoat: Color::black(),
                     emission: Color::black(),
+                    albedo: Color::black(),
+                    normal: Color::black(),
                 }
             }
   
@@ -133,40 +255,35 @@ pub fn ray_color(
 
             let unit_direction = Vec3::normalize(&r.direction);
             let t = 0.5 * (unit_direction.y() + 1.0);
-            let gradient_color = Color::new(0.63, 0.75, 1.0, if hide_skydome {0.0} else {1.0});
-            let gradient = Color::new(1.0, 1.0, 1.0, if hide_skydome {0.0} else {1.0}) * (1.0 - t) + gradient_color * t;
+            let gradient_color = Color::new(0.63, 0.75, 1.0, if ctx.hide_skydome {0.0} else {1.0});
+            let gradient = Color::new(1.0, 1.0, 1.0, if ctx.hide_skydome {0.0} else {1.0}) * (1.0 - t) + gradient_color * t;
             return Lobes {
                 rgba: Color::black(),// gradient_color*gradient_color,
                 diffuse: Color::black(),
                 specular: Color::black(),
+                clearcoat: Color::black(),
                 emission: Color::black(),
+                albedo: Color::black(),
+                normal: Color::black(),
             }
         }
     }
 }
 
-pub fn render_chunk(
-    pixel_chunks: &Vec<(u32, u32)>,
-    height: u32,
-    width: u32,
-    sample: &u16,
-    camera: &Arc<Camera>,
-    bvh: &Object,
-    quad_lights: &Arc<Vec<Object>>,
-    dir_lights: &Arc<Vec<DirectionalLight>>,
-    depth: u32,
-    max_depth: u32,
-    progressive: bool,
-    skydome: &Option<Arc<TextureMap>>,
-    hide_skydome: bool,
-    ) -> Vec<(u32, u32, Lobes)> {
+// camera rays that hit something are fully covered, cutouts average out to fractional alpha
+fn coverage(depth: u32, max_depth: u32, alpha: f64) -> f64 {
+    if depth == max_depth {1.0} else {alpha}
+}
+
+pub fn render_chunk(pixel_chunks: &Vec<(u32, u32)>, height: u32, width: u32, camera: &Arc<Camera>, ctx: &RenderContext) -> Vec<(u32, u32, Lobes)> {
         let mut pixel_colors = Vec::new();
         for pixel in pixel_chunks {
             let (x, y) = pixel;               
             let u = (*x as f64 + random_float()) / ((width - 1) as f64);
             let v = 1.0 - ((*y as f64 + random_float()) / ((height - 1) as f64));
-            let r = camera.get_ray(u, v);
-            let color = ray_color(&r, bvh, quad_lights, dir_lights, depth, max_depth, progressive, skydome, hide_skydome);
+            let mut r = camera.get_ray(u, v);
+            r.cone_spread = camera.pixel_spread(height);
+            let color = ray_color(&r, ctx, ctx.max_depth, ctx.fog);
             pixel_colors.push((*x, *y, color));
         }
         pixel_colors
@@ -1,34 +1,34 @@
 extern crate num_cpus;
-use crate::render::{ray_color, get_pixel_chunks, render_chunk};
+use crate::render::{get_pixel_chunks, render_chunk, RenderContext};
 use crate::bvh::Bvh;
 use crate::camera::Camera;
-use crate::hit::{HitRecord, HittableList, Object, Hittable};
-use crate::material::{Emits, Light, Material, Principle, Scatterable};
-use crate::ray::Ray;
+use crate::hit::{HittableList, Object};
+use crate::material::{Material, Principle, Mix, Layer, conductor_preset};
 use crate::sphere::Sphere;
-use crate::tri::Tri;
-use crate::utility::{random_float, random_range, INF};
+use crate::tri::{Tri, generate_tangents};
 use crate::vec3::Vec3;
 use crate::vec2::Vec2;
 use crate::color::Color;
-use crate::aabb::Aabb;
 use crate::buffers::{Lobes, FrameBuffers};
-use image::{DynamicImage, ImageBuffer, Rgb, Rgba, RgbImage, RgbaImage, Rgb32FImage, Rgba32FImage};
+use image::{ImageBuffer, Rgba, RgbaImage, Rgba32FImage};
 use indicatif::{ProgressBar, ProgressStyle};
-use serde_json::{Result, Value};
+use serde_json::Value;
 use show_image::{create_window, ImageInfo, ImageView, WindowOptions};
 use std::io::Write;
 use std::collections::HashMap;
-use std::time::Duration;
-use std::{env, fs, thread};
-use std::sync::{Arc, Mutex, RwLock};
-use std::mem::drop;
+use std::{fs, thread};
+use std::sync::Arc;
 use crate::texture::TextureMap;
-use rayon::prelude::*;
+use crate::texture_cache::texture_cache;
+use crate::shader::Shader;
 use crate::lights::{QuadLight, DirectionalLight};
+use crate::denoise::Denoiser;
+use crate::subsurface::SubsurfaceMode;
+use crate::medium::Medium;
+use crate::grid::DensityGrid;
 
 
-pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
+pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> Result<(), String> {
 
     print!("Processing scene...");
     let mut data: serde_json::Value = serde_json::Value::Null;
@@ -41,7 +41,8 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
     }
 
     // extract render settings
-    let progressive = data["settings"]["progressive"].as_u64().unwrap() == 1;
+    // exported by the scene writer, the renderer is always progressive for now
+    let _progressive = data["settings"]["progressive"].as_u64().unwrap() == 1;
     let aspect_ratio = data["settings"]["aspect_ratio"].as_f64().unwrap();
     let width = data["settings"]["width"].as_u64().unwrap() as u32;
     let height = (width as f64 / aspect_ratio) as u32;
@@ -75,6 +76,19 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
     ));
     let spp: u16 = data["settings"]["spp"].as_u64().unwrap() as u16;
     let depth: u32 = data["settings"]["depth"].as_u64().unwrap() as u32;
+    let denoise = data["settings"]["denoise"].as_u64().unwrap_or(0) == 1;
+    let fog = load_medium(&data["settings"]["fog"]).map_err(|e| format!("fog: {e}"))?;
+    if let Some(mb) = data["settings"]["texture_cache_mb"].as_u64() {
+        texture_cache().set_budget(mb as usize * 1024 * 1024);
+    }
+    let default_denoiser = Denoiser::default();
+    let denoiser = Denoiser::new(
+        data["settings"]["denoise_radius"].as_i64().map(|r| r as i32).unwrap_or(default_denoiser.radius),
+        default_denoiser.patch_radius,
+        data["settings"]["denoise_strength"].as_f64().map(|s| s as f32).unwrap_or(default_denoiser.strength),
+        default_denoiser.albedo_sigma,
+        default_denoiser.normal_sigma,
+    );
 
     // create framebuffers and viewer
     let mut preview: RgbaImage = ImageBuffer::new(width, height);
@@ -138,72 +152,105 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
             mat["emission"][2].as_f64().unwrap(),
             1.0
         );
-        let bump = mat["bump"][0].as_f64().unwrap();
         let bump_strength = mat["bump_strength"].as_f64().unwrap();
         let normal_strength = mat["normal_strength"].as_f64().unwrap();
-        
-        // textures
-        let mut diffuse_tex = None;
-        let dt = mat["diffuse_tex"].to_string().replace(['"'], "");
-        if dt != "" {            
-            diffuse_tex = Some(TextureMap::new(&dt, true))
-        };
-
-        let mut diffuse_weight_tex = None;
-        let dwt = mat["diffuse_weight_tex"].to_string().replace(['"'], "");
-        if dwt != "" {
-            diffuse_weight_tex = Some(TextureMap::new(&dwt, true))
-        };
-
-        let mut specular_tex = None;
-        let st = mat["specular_tex"].to_string().replace(['"'], "");
-        if st != "" {
-            specular_tex = Some(TextureMap::new(&st, true))
-        };
-
-        let mut specular_weight_tex = None;
-        let swt = mat["specular_weight_tex"].to_string().replace(['"'], "");
-        if swt != "" {
-            specular_weight_tex = Some(TextureMap::new(&swt, true))
-        };
-
-        let mut roughness_tex = None;
-        let rt = mat["roughness_tex"].to_string().replace(['"'], "");
-        if rt != "" {
-            roughness_tex = Some(TextureMap::new(&rt, true))
-        };
-
-        let mut metallic_tex = None;
-        let mt = mat["metallic_tex"].to_string().replace(['"'], "");
-        if mt != "" {
-            metallic_tex = Some(TextureMap::new(&mt, true))
-        };
-
-        let mut refraction_tex = None;
-        let rft = mat["refraction_tex"].to_string().replace(['"'], "");
-        if rft != "" {
-            refraction_tex = Some(TextureMap::new(&rft, true))
-        };
-
-        let mut emission_tex = None;
-        let et = mat["emission_tex"].to_string().replace(['"'], "");
-        if et != "" {
-            emission_tex = Some(TextureMap::new(&et, true))
-        };
+        let transmission = mat["transmission"]
+            .as_array()
+            .map(|c| Color::new(
+                c[0].as_f64().unwrap(),
+                c[1].as_f64().unwrap(),
+                c[2].as_f64().unwrap(),
+                1.0
+            ))
+            .unwrap_or(Color::white());
+        let transmission_depth = mat["transmission_depth"].as_f64().unwrap_or(0.0);
+        let anisotropy = mat["anisotropy"][0].as_f64().unwrap_or(0.0);
+        let anisotropy_rotation = mat["anisotropy_rotation"][0].as_f64().unwrap_or(0.0);
+        let clearcoat = mat["clearcoat"][0].as_f64().unwrap_or(0.0);
+        let clearcoat_roughness = mat["clearcoat_roughness"][0].as_f64().unwrap_or(0.1);
+        let clearcoat_ior = mat["clearcoat_ior"].as_f64().unwrap_or(1.5);
+        let thin_film_thickness = mat["thin_film_thickness"].as_f64().unwrap_or(0.0);
+        let thin_film_ior = mat["thin_film_ior"].as_f64().unwrap_or(1.33);
+        let diffuse_roughness = mat["diffuse_roughness"][0].as_f64().unwrap_or(0.0);
+        let opacity = mat["opacity"][0].as_f64().unwrap_or(1.0);
+        let thin_walled = mat["thin_walled"].as_u64() == Some(1);
+        let diffuse_transmission = mat["diffuse_transmission"][0].as_f64().unwrap_or(0.0);
+        let metal_edge_tint = mat["metal_edge_tint"]
+            .as_array()
+            .map(|c| Color::new(
+                c[0].as_f64().unwrap(),
+                c[1].as_f64().unwrap(),
+                c[2].as_f64().unwrap(),
+                1.0
+            ))
+            .unwrap_or(Color::white());
 
-        let mut bump_tex = None;
-        let bt = mat["bump_tex"].to_string().replace(['"'], "");
-        if bt != "" {
-            bump_tex = Some(TextureMap::new(&bt, true))
+        // a named preset wins over an explicit complex ior, without either metals use the edge tint fit
+        let metal_ior = match mat["metal_preset"].as_str() {
+            Some(preset) if !preset.is_empty() => {
+                let ior = conductor_preset(preset);
+                if ior.is_none() {
+                    println!("Unknown metal preset {}, using edge tint instead.", preset);
+                }
+                ior
+            },
+            _ => match (mat["metal_eta"].as_array(), mat["metal_k"].as_array()) {
+                (Some(eta), Some(k)) => Some((
+                    Color::new(eta[0].as_f64().unwrap(), eta[1].as_f64().unwrap(), eta[2].as_f64().unwrap(), 1.0),
+                    Color::new(k[0].as_f64().unwrap(), k[1].as_f64().unwrap(), k[2].as_f64().unwrap(), 1.0),
+                )),
+                _ => None,
+            },
         };
-
-        let mut normal_tex = None;
-        let nt = mat["normal_tex"].to_string().replace(['"'], "");
-        if nt != "" {
-            normal_tex = Some(TextureMap::new(&nt, true))
+        let sheen = mat["sheen"][0].as_f64().unwrap_or(0.0);
+        let sheen_color = mat["sheen_color"]
+            .as_array()
+            .map(|c| Color::new(
+                c[0].as_f64().unwrap(),
+                c[1].as_f64().unwrap(),
+                c[2].as_f64().unwrap(),
+                1.0
+            ))
+            .unwrap_or(Color::white());
+        let sheen_roughness = mat["sheen_roughness"][0].as_f64().unwrap_or(0.3);
+        let subsurface = mat["subsurface"][0].as_f64().unwrap_or(0.0);
+        let subsurface_color = mat["subsurface_color"]
+            .as_array()
+            .map(|c| Color::new(
+                c[0].as_f64().unwrap(),
+                c[1].as_f64().unwrap(),
+                c[2].as_f64().unwrap(),
+                1.0
+            ))
+            .unwrap_or(Color::white());
+        let subsurface_radius = mat["subsurface_radius"]
+            .as_array()
+            .map(|c| Color::new(
+                c[0].as_f64().unwrap(),
+                c[1].as_f64().unwrap(),
+                c[2].as_f64().unwrap(),
+                1.0
+            ))
+            .unwrap_or(Color::new(1.0, 0.2, 0.1, 1.0));
+        let subsurface_anisotropy = mat["subsurface_anisotropy"].as_f64().unwrap_or(0.0);
+        let subsurface_mode = match mat["subsurface_mode"].as_u64() {
+            Some(1) => SubsurfaceMode::Diffusion,
+            _ => SubsurfaceMode::RandomWalk,
         };
+        
+        // textures
+        let diffuse_tex = load_texture(mat, "diffuse_tex")?;
+        let diffuse_weight_tex = load_texture(mat, "diffuse_weight_tex")?;
+        let specular_tex = load_texture(mat, "specular_tex")?;
+        let specular_weight_tex = load_texture(mat, "specular_weight_tex")?;
+        let roughness_tex = load_texture(mat, "roughness_tex")?;
+        let metallic_tex = load_texture(mat, "metallic_tex")?;
+        let refraction_tex = load_texture(mat, "refraction_tex")?;
+        let emission_tex = load_texture(mat, "emission_tex")?;
+        let bump_tex = load_texture(mat, "bump_tex")?;
+        let normal_tex = load_normal_texture(mat, "normal_tex")?;
 
-        let material = Material::Principle(Principle::new(
+        let mut principle = Principle {
             diffuse,
             diffuse_weight,
             specular,
@@ -213,23 +260,96 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
             metallic,
             refraction,
             emission,
-            bump,
             bump_strength,
             normal_strength,
-            diffuse_tex,
-            diffuse_weight_tex,
-            specular_tex,
-            specular_weight_tex,
-            roughness_tex,
-            metallic_tex,
-            refraction_tex,
-            emission_tex,
-            bump_tex,
-            normal_tex
-        ));
+            diffuse_texture: diffuse_tex,
+            diffuse_weight_texture: diffuse_weight_tex,
+            specular_texture: specular_tex,
+            specular_weight_texture: specular_weight_tex,
+            roughness_texture: roughness_tex,
+            metallic_texture: metallic_tex,
+            refraction_texture: refraction_tex,
+            emission_texture: emission_tex,
+            bump_texture: bump_tex,
+            normal_texture: normal_tex,
+            ..Principle::default()
+        };
+        principle.transmission = transmission;
+        principle.transmission_depth = transmission_depth;
+        principle.anisotropy = anisotropy;
+        principle.anisotropy_rotation = anisotropy_rotation;
+        principle.anisotropy_texture = load_texture(mat, "anisotropy_tex")?;
+        principle.anisotropy_rotation_texture = load_texture(mat, "anisotropy_rotation_tex")?;
+        principle.clearcoat = clearcoat;
+        principle.clearcoat_roughness = clearcoat_roughness;
+        principle.clearcoat_ior = clearcoat_ior;
+        principle.clearcoat_texture = load_texture(mat, "clearcoat_tex")?;
+        principle.clearcoat_roughness_texture = load_texture(mat, "clearcoat_roughness_tex")?;
+        principle.clearcoat_normal_texture = load_normal_texture(mat, "clearcoat_normal_tex")?;
+        principle.thin_film_thickness = thin_film_thickness;
+        principle.thin_film_ior = thin_film_ior;
+        principle.thin_film_thickness_texture = load_texture(mat, "thin_film_thickness_tex")?;
+        principle.diffuse_roughness = diffuse_roughness;
+        principle.diffuse_roughness_texture = load_texture(mat, "diffuse_roughness_tex")?;
+        principle.opacity = opacity;
+        principle.opacity_texture = load_texture(mat, "opacity_tex")?;
+        principle.thin_walled = thin_walled;
+        principle.diffuse_transmission = diffuse_transmission;
+        principle.diffuse_transmission_texture = load_texture(mat, "diffuse_transmission_tex")?;
+        principle.metal_edge_tint = metal_edge_tint;
+        principle.metal_ior = metal_ior;
+        principle.metal_edge_tint_texture = load_texture(mat, "metal_edge_tint_tex")?;
+        principle.sheen = sheen;
+        principle.sheen_color = sheen_color;
+        principle.sheen_roughness = sheen_roughness;
+        principle.sheen_texture = load_texture(mat, "sheen_tex")?;
+        principle.sheen_color_texture = load_texture(mat, "sheen_color_tex")?;
+        principle.sheen_roughness_texture = load_texture(mat, "sheen_roughness_tex")?;
+        principle.subsurface = subsurface;
+        principle.subsurface_color = subsurface_color;
+        principle.subsurface_radius = subsurface_radius;
+        principle.subsurface_anisotropy = subsurface_anisotropy;
+        principle.subsurface_mode = subsurface_mode;
+        principle.subsurface_texture = load_texture(mat, "subsurface_tex")?;
+        principle.subsurface_color_texture = load_texture(mat, "subsurface_color_tex")?;
+        principle.medium = load_medium(&mat["medium"]).map_err(|e| format!("material {name}, medium: {e}"))?;
+
+        // volume materials only mark the boundary of their medium
+        let material = match (&principle.medium, mat["volume"].as_u64()) {
+            (Some(medium), Some(1)) => Material::Volume(medium.clone()),
+            _ => Material::Principle(Box::new(principle)),
+        };
         scene_materials.insert(name, Arc::new(material));
     }
 
+    // mixes and layers reference other materials by name, so they load afterwards and in order
+    if let Some(composites) = data["scene"]["composite_materials"].as_array() {
+        for mat in composites.iter() {
+            let name = mat["name"].to_string().replace(['"'], "");
+            let find = |key: &str| {
+                let target = mat[key].as_str().unwrap_or("");
+                scene_materials.get(target)
+                    .cloned()
+                    .unwrap_or_else(|| panic!("Unknown material {} in {}.", target, name))
+            };
+            let material = match mat["type"].as_str() {
+                Some("layer") => Material::Layer(Layer::new(
+                    find("coat"),
+                    find("base"),
+                    mat["weight"][0].as_f64().unwrap_or(1.0),
+                    load_texture(mat, "weight_tex")?,
+                )),
+                _ => Material::Mix(Mix::new(
+                    find("a"),
+                    find("b"),
+                    mat["mask"][0].as_f64().unwrap_or(0.5),
+                    load_texture(mat, "mask_tex")?,
+                )),
+            };
+            scene_materials.insert(name, Arc::new(material));
+        }
+    }
+
     println!("Processing meshes...");
     // get tris
     let mesh_count = data["scene"]["mesh_count"].as_u64().unwrap();
@@ -243,6 +363,28 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
         let uv_array = &data["scene"]["meshes"][obj as usize]["uvs"]
         .as_array()
         .unwrap();
+        // object space positions for procedural textures, world space when not exported
+        let object_array = data["scene"]["meshes"][obj as usize]["object_vertices"]
+            .as_array()
+            .unwrap_or(vtx_array);
+        let object_vertex = |face: usize, corner: usize| Vec3::new(
+            object_array[face][corner][0].as_f64().unwrap(),
+            object_array[face][corner][1].as_f64().unwrap(),
+            object_array[face][corner][2].as_f64().unwrap(),
+        );
+        // imported [x, y, z, bitangent sign] tangents per corner, generated once the mesh is built otherwise
+        let tangent_array = data["scene"]["meshes"][obj as usize]["tangents"].as_array();
+        let corner_tangents = |face: usize, corners: [usize; 3]| {
+            let tangents = tangent_array.unwrap();
+            let tangent = |corner: usize| Vec3::new(
+                tangents[face][corner][0].as_f64().unwrap(),
+                tangents[face][corner][1].as_f64().unwrap(),
+                tangents[face][corner][2].as_f64().unwrap(),
+            );
+            let sign = |corner: usize| if tangents[face][corner][3].as_f64().unwrap_or(1.0) < 0.0 {-1.0} else {1.0};
+            (corners.map(tangent).to_vec(), corners.map(sign).to_vec())
+        };
+        let mut mesh_tris = Vec::new();
         for i in 0..vtx_array.len() {
             let p0 = Vec3::new(
                 vtx_array[i][0][0].as_f64().unwrap(),
@@ -293,8 +435,14 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
                 .to_string()
                 .replace(['"'], "");
             let material = scene_materials.get(material_name).unwrap();
-            let new_tri = Object::Tri(Tri::new(vertices, normals, uvs, material.clone(), true));
-            world.objects.push(Arc::new(new_tri));
+            let mut new_tri = Tri::new(vertices, normals, uvs, material.clone(), true);
+            new_tri.object_id = obj as usize + 1;
+            new_tri.set_object_vertices(vec![object_vertex(i, 0), object_vertex(i, 1), object_vertex(i, 2)]);
+            if tangent_array.is_some() {
+                let (tangents, signs) = corner_tangents(i, [0, 1, 2]);
+                new_tri.set_tangents(tangents, signs);
+            }
+            mesh_tris.push(new_tri);
             if vtx_array[i].as_array().unwrap().len() == 4 {
                 let p3 = Vec3::new(
                     vtx_array[i][3][0].as_f64().unwrap(),
@@ -313,11 +461,23 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
                 let vertices = vec![p2, p3, p0];
                 let normals = vec![n2, n3, n0];
                 let uvs = vec![uv2, uv3, uv0];
-                let quad_tri = Object::Tri(Tri::new(vertices, normals, uvs, material.clone(), true));
-                world.objects.push(Arc::new(quad_tri));
+                let mut quad_tri = Tri::new(vertices, normals, uvs, material.clone(), true);
+                quad_tri.object_id = obj as usize + 1;
+                quad_tri.set_object_vertices(vec![object_vertex(i, 2), object_vertex(i, 3), object_vertex(i, 0)]);
+                if tangent_array.is_some() {
+                    let (tangents, signs) = corner_tangents(i, [2, 3, 0]);
+                    quad_tri.set_tangents(tangents, signs);
+                }
+                mesh_tris.push(quad_tri);
             }
             
         }
+        if tangent_array.is_none() {
+            generate_tangents(&mut mesh_tris);
+        }
+        for tri in mesh_tris {
+            world.objects.push(Arc::new(Object::Tri(tri)));
+        }
     }
 
     // get spheres
@@ -335,7 +495,7 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
         let z = data["scene"]["spheres"][obj as usize]["location"][2]
             .as_f64()
             .unwrap();
-        let new_sphere = Object::Sphere(Sphere::new(
+        let mut new_sphere = Sphere::new(
             Vec3::new(x, y, z),
             Vec3::new(x, y, z),
             0.0,
@@ -344,8 +504,9 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
                 .as_f64()
                 .unwrap(),
             scene_materials.get(material_name).unwrap().clone()
-        ));
-        world.objects.push(Arc::new(new_sphere));
+        );
+        new_sphere.object_id = (mesh_count + obj) as usize + 1;
+        world.objects.push(Arc::new(Object::Sphere(new_sphere)));
     }
 
     // get quad lights
@@ -449,33 +610,27 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
         if sample != 0 {
             progress.inc(1);
         }
-        let skydome_texture = Arc::new(TextureMap::new("g:/rust_projects/krrust/textures/alley_01.jpg", true));
+        // not hooked up yet, so a missing file leaves it out rather than failing
+        let skydome_texture = TextureMap::new("g:/rust_projects/krrust/textures/alley_01.jpg", true).ok().map(Arc::new);
         let mut handles = Vec::with_capacity(num_threads);
         for chunk in pixel_chunks.chunks(thread_chunk_size).map(|c| c.to_vec()) {
             let camera = camera.clone();
             let world_bvh = world_bvh.clone();
             let quad_lights = quad_lights.clone();
             let dir_lights = dir_lights.clone();
+            let fog = fog.clone();
             let sky = skydome_texture.clone();
             let handle = thread::spawn(move || {
-                let result = chunk.iter().map(|c|
-                    render_chunk(
-                        c,
-                        height,
-                        width,
-                        &sample,
-                        &camera,
-                        &world_bvh,
-                        &quad_lights,
-                        &dir_lights,
-                        depth,
-                        depth,
-                        progressive,
-                        &None,//&Some(sky.clone()),
-                        false,
-                        )
-                    ).collect::<Vec<Vec<(u32, u32, Lobes)>>>();
-                result
+                let ctx = RenderContext {
+                    world: &world_bvh,
+                    quad_lights: &quad_lights,
+                    dir_lights: &dir_lights,
+                    max_depth: depth,
+                    skydome: &None,//&sky,
+                    hide_skydome: false,
+                    fog: &fog,
+                };
+                chunk.iter().map(|c| render_chunk(c, height, width, &camera, &ctx)).collect::<Vec<Vec<(u32, u32, Lobes)>>>()
             });
             handles.push(handle);
         }
@@ -486,12 +641,14 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
                 for pixel in chunk_result{
                     let (x, y, color) = (pixel.0, pixel.1, pixel.2);
                     let (mut rgba, mut diff, mut spec) = (color.rgba, color.diffuse, color.specular);
+                    let (mut coat, mut albedo, mut normal) = (color.clearcoat, color.albedo, color.normal);
                     let previous = buffers.get_pixel(x, y);
                     let (previous_rgba, previous_diff, previous_spec) = (
                         previous.rgba, 
                         previous.diffuse, 
                         previous.specular
                     );
+                    buffers.put_split_sample(x, y, sample, rgba);
 
                     // average in new sample
                     if sample > 0 {
@@ -499,9 +656,13 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
                         rgba = (rgba + (previous_rgba * sample as f64)) / average;
                         diff = (diff + (previous_diff * sample as f64)) / average;
                         spec = (spec + (previous_spec * sample as f64)) / average;
+                        coat = (coat + (previous.clearcoat * sample as f64)) / average;
+                        albedo = (albedo + (previous.albedo * sample as f64)) / average;
+                        normal = (normal + (previous.normal * sample as f64)) / average;
                     }
 
-                    buffers.put_pixel(x, y, rgba, diff, spec);
+                    buffers.put_pixel(x, y, rgba, diff, spec, coat);
+                    buffers.put_features(x, y, albedo, normal);
                     preview.put_pixel(
                         x,
                         y,
@@ -533,5 +694,129 @@ pub fn render_scene(scene_file: Option<&str>, output_dir: &str) -> () {
         preview.save(&output_file);
     }
     // buffers.rgba.save(&output);
-    ProgressBar::finish_with_message(&progress, "% Render complete")
+    ProgressBar::finish_with_message(&progress, "% Render complete");
+    let stats = texture_cache().stats();
+    println!(
+        "Texture cache: {} textures, {} hits, {} misses, {} loads, {} evictions, {:.1} MB resident, {:.1} MB peak",
+        stats.textures, stats.hits, stats.misses, stats.loads, stats.evictions,
+        stats.memory as f64 / (1024.0 * 1024.0), stats.peak as f64 / (1024.0 * 1024.0),
+    );
+
+    // the raw beauty is always written, denoising adds its result alongside
+    let raw_output = output_dir.to_owned() + "krust_render.exr";
+    if let Err(e) = buffers.rgba.save(&raw_output) {
+        eprintln!("{raw_output}: {e}");
+    }
+    if denoise {
+        println!("Denoising...");
+        let denoised = denoiser.denoise(&buffers);
+        let mut preview_denoised: RgbaImage = ImageBuffer::new(width, height);
+        for (x, y, pixel) in denoised.enumerate_pixels() {
+            preview_denoised.put_pixel(
+                x,
+                y,
+                Rgba([
+                    (pixel[0].max(0.0).sqrt().min(1.0) * 255.999) as u8,
+                    (pixel[1].max(0.0).sqrt().min(1.0) * 255.999) as u8,
+                    (pixel[2].max(0.0).sqrt().min(1.0) * 255.999) as u8,
+                    u8::MAX,
+                ]),
+            );
+        }
+        let render_view = ImageView::new(ImageInfo::rgba8(width, height), &preview_denoised);
+        if let Err(e) = window.as_ref().expect("REASON").set_image("image-001", render_view) {
+            eprintln!("denoised preview: {e}");
+        }
+        let preview_output = output_dir.to_owned() + "krust_render_denoised.png";
+        if let Err(e) = preview_denoised.save(&preview_output) {
+            eprintln!("{preview_output}: {e}");
+        }
+        let denoised_output = output_dir.to_owned() + "krust_render_denoised.exr";
+        if let Err(e) = denoised.save(&denoised_output) {
+            eprintln!("{denoised_output}: {e}");
+        }
+    }
+    Ok(())
+}
+
+// optional texture slots hold a texture path or a shading network (see Shader::from_json),
+// a missing key or empty path leaves the slot untextured. errors name the material and slot
+fn load_texture(mat: &Value, key: &str) -> Result<Option<Shader>, String> {
+    let shader = match &mat[key] {
+        Value::String(path) if !path.is_empty() => TextureMap::new(path, true).map(Shader::texture),
+        network @ Value::Object(_) => Shader::from_json(network),
+        _ => return Ok(None)
+    };
+    shader.map(Some).map_err(|e| format!("material {}, {key}: {e}", mat["name"].as_str().unwrap_or("")))
+}
+
+// normal maps hold vectors rather than colors, so plain paths skip the srgb conversion
+fn load_normal_texture(mat: &Value, key: &str) -> Result<Option<Shader>, String> {
+    match &mat[key] {
+        Value::String(path) if !path.is_empty() => TextureMap::new(path, false)
+            .map(|map| Some(Shader::texture(map)))
+            .map_err(|e| format!("material {}, {key}: {e}", mat["name"].as_str().unwrap_or(""))),
+        _ => load_texture(mat, key)
+    }
+}
+
+// optional participating medium, coefficients default to a clear medium
+fn load_medium(medium: &Value) -> Result<Option<Arc<Medium>>, String> {
+    if !medium.is_object() {
+        return Ok(None)
+    }
+    let color = |key: &str| medium[key]
+        .as_array()
+        .map(|c| Color::new(
+            c[0].as_f64().unwrap(),
+            c[1].as_f64().unwrap(),
+            c[2].as_f64().unwrap(),
+            1.0
+        ))
+        .unwrap_or(Color::new(0.0, 0.0, 0.0, 1.0));
+    let mut volume = Medium::new(
+        color("absorption"),
+        color("scattering"),
+        color("emission"),
+        medium["anisotropy"].as_f64().unwrap_or(0.0),
+        medium["density"].as_f64().unwrap_or(1.0),
+    );
+
+    // heterogeneous density and temperature from voxel grids
+    volume.grid = load_grid(&medium["grid"]).map_err(|e| format!("grid: {e}"))?;
+    volume.temperature = load_grid(&medium["temperature"]).map_err(|e| format!("temperature: {e}"))?;
+    volume.temperature_scale = medium["temperature_scale"].as_f64().unwrap_or(1.0);
+    volume.blackbody_intensity = medium["blackbody_intensity"].as_f64().unwrap_or(1.0);
+    Ok(Some(Arc::new(volume)))
+}
+
+// voxel grids are either a path to a grid file or an inline dense grid
+fn load_grid(grid: &Value) -> Result<Option<Arc<DensityGrid>>, String> {
+    if let Some(path) = grid.as_str() {
+        let grid = DensityGrid::load(path).map_err(|e| format!("{path}: {e}"))?;
+        return Ok(Some(Arc::new(grid)))
+    }
+    if !grid.is_object() {
+        return Ok(None)
+    }
+    let vec3 = |key: &str| Vec3::new(
+        grid[key][0].as_f64().unwrap(),
+        grid[key][1].as_f64().unwrap(),
+        grid[key][2].as_f64().unwrap(),
+    );
+    let resolution = [0, 1, 2].map(|i| grid["resolution"][i].as_u64().unwrap() as usize);
+    let values = grid["values"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_f64().unwrap() as f32)
+        .collect::<Vec<_>>();
+    let voxels = resolution.iter().product::<usize>();
+    if voxels == 0 {
+        return Err("empty voxel grid".to_string())
+    }
+    if values.len() != voxels {
+        return Err(format!("inline grid has {} values, its resolution needs {voxels}", values.len()))
+    }
+    Ok(Some(Arc::new(DensityGrid::new(resolution, vec3("min"), vec3("max"), values))))
 }
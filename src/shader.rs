@@ -0,0 +1,672 @@
+use crate::vec3::Vec3;
+use crate::vec2::Vec2;
+use crate::color::Color;
+use crate::texture::{TextureMap, Filter, Wrap, MipFilter};
+use crate::ray::Ray;
+use crate::material::fresnel_dielectric;
+use crate::noise::{perlin, simplex, fbm, turbulence, worley, hash_float};
+use crate::hit::HitRecord;
+use crate::mat3::Mat3;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+// everything a shading network can read at a hit. the footprint is the ray cone's
+// ellipse on the surface as its long and short axes in uv, footprint_world the same axes in world space
+#[derive(Debug, Clone, Copy)]
+pub struct ShadingPoint {
+    pub uv: Vec2,
+    pub point: Vec3,
+    pub object_point: Vec3,
+    pub object_to_world: Mat3,
+    pub normal: Vec3,
+    pub view: Vec3,
+    pub footprint: (Vec2, Vec2),
+    pub footprint_world: (Vec3, Vec3),
+}
+
+impl ShadingPoint {
+    pub fn new(rec: &HitRecord, r: &Ray) -> Self {
+        let view = -r.direction.normalize();
+        let width = r.cone_width_at(rec.t);
+
+        // the cone stretches by 1 / cos along the view direction projected onto the surface
+        let cos = f64::max(view.dot(&rec.normal).abs(), 0.05);
+        let along = view - rec.normal * view.dot(&rec.normal);
+        let along = if along.length_squared() > 1e-12 {along.normalize()} else {rec.normal.tangent_bitangent().0};
+        let across = rec.normal.cross(&along);
+
+        // world space tangent vectors to uv through the metric of dP/du and dP/dv
+        let (g11, g12, g22) = (rec.dpdu.dot(&rec.dpdu), rec.dpdu.dot(&rec.dpdv), rec.dpdv.dot(&rec.dpdv));
+        let det = g11 * g22 - g12 * g12;
+        let to_uv = |x: Vec3| {
+            if det.abs() < 1e-20 {
+                return Vec2::zero()
+            }
+            let (b1, b2) = (x.dot(&rec.dpdu), x.dot(&rec.dpdv));
+            Vec2::new(((g22 * b1 - g12 * b2) / det) as f32, ((g11 * b2 - g12 * b1) / det) as f32)
+        };
+
+        let footprint_world = (along * (width / cos), across * width);
+        Self {
+            uv: rec.uv,
+            point: rec.point,
+            object_point: rec.object_point,
+            object_to_world: rec.object_to_world,
+            normal: rec.normal,
+            view,
+            footprint: (to_uv(footprint_world.0), to_uv(footprint_world.1)),
+            footprint_world,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Space {
+    Uv,
+    World,
+    Object,
+}
+
+// where a procedural pattern reads its coordinates, uv lookups sit on the z = 0 plane
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub space: Space,
+    pub frequency: f64,
+    pub offset: Vec3,
+}
+
+impl Placement {
+    pub fn point(&self, sp: &ShadingPoint) -> Vec3 {
+        let p = match self.space {
+            Space::Uv => Vec3::new(sp.uv.x as f64, sp.uv.y as f64, 0.0),
+            Space::World => sp.point,
+            Space::Object => sp.object_point,
+        };
+        p * self.frequency + self.offset
+    }
+}
+
+// node inputs are either unconnected values or another node's output
+#[derive(Debug, Clone)]
+pub enum Input {
+    Constant(Color),
+    Node(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Minimum,
+    Maximum,
+    Invert,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum VoronoiOutput {
+    F1,
+    F2,
+    Edge,
+    Cell,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GradientKind {
+    Linear,
+    Radial,
+    Spherical,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Constant(Color),
+    // rotate in degrees about the uv center, then repeat and offset like a place2dTexture.
+    // filtering and wrapping are set on the map
+    Texture { map: Arc<TextureMap>, repeat: [f64; 2], offset: [f64; 2], rotate: f64 },
+    Math { op: MathOp, a: Input, b: Input },
+    // hue shift in degrees, saturation and gain multiply, gamma is applied as 1 / gamma
+    ColorCorrect { input: Input, hue: f64, saturation: f64, gamma: f64, gain: f64 },
+    // positions come from the input's red channel, or the u or v coordinate when nothing is connected
+    Ramp { input: Option<Input>, along_u: bool, stops: Vec<(f64, Input)>, interpolation: Interpolation },
+    Mix { a: Input, b: Input, mask: Input },
+    Noise { placement: Placement },
+    // fractal perlin or simplex noise, turbulence sums absolute octaves
+    Fbm { placement: Placement, basis: fn(Vec3) -> f64, octaves: u32, lacunarity: f64, gain: f64, turbulence: bool },
+    Voronoi { placement: Placement, jitter: f64, output: VoronoiOutput },
+    // alternates a and b between unit cells of the placement
+    Checker { placement: Placement, a: Input, b: Input },
+    // size is one brick in placement units, every other row shifts by row_offset of a brick
+    Bricks { placement: Placement, size: [f64; 2], mortar_width: f64, row_offset: f64, brick: Input, mortar: Input },
+    // linear runs along x, radial is the angle about z and spherical falls off from the origin
+    Gradient { placement: Placement, kind: GradientKind },
+    // the input projected along the x, y and z axes of world or object space, blended by the normal
+    // with weights |n|^sharpness. as a normal map the input is a tangent space normal per projection
+    Triplanar { input: Input, placement: Placement, sharpness: f64, normal_map: bool },
+    Fresnel { ior: f64 },
+    FacingRatio,
+}
+
+// a shading network evaluated per hit, plain texture paths load as a single texture node
+#[derive(Debug, Clone)]
+pub struct Shader {
+    pub nodes: Vec<Node>,
+    pub output: usize,
+}
+
+impl Shader {
+    pub fn texture(map: TextureMap) -> Self {
+        Self {
+            nodes: vec![Node::Texture { map: Arc::new(map), repeat: [1.0, 1.0], offset: [0.0, 0.0], rotate: 0.0 }],
+            output: 0,
+        }
+    }
+
+    // {"nodes": {name: {"type": ..., params}}, "output": name}
+    // params are numbers, [r, g, b] arrays or the name of another node
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let nodes = value["nodes"].as_object().ok_or("shader has no nodes")?;
+        let names: HashMap<&str, usize> = nodes.keys().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+        let output = value["output"].as_str()
+            .and_then(|name| names.get(name).copied())
+            .ok_or("shader output is not one of its nodes")?;
+
+        let shader = Self {
+            nodes: nodes.iter().map(|(name, node)| parse_node(node, &names).map_err(|e| format!("{name}: {e}"))).collect::<Result<Vec<Node>, String>>()?,
+            output,
+        };
+        shader.check_cycles()?;
+        Ok(shader)
+    }
+
+    fn check_cycles(&self) -> Result<(), String> {
+        // 0 unvisited, 1 on the current path, 2 done
+        fn visit(shader: &Shader, index: usize, state: &mut Vec<u8>) -> Result<(), String> {
+            match state[index] {
+                1 => return Err("shader network has a cycle".to_string()),
+                2 => return Ok(()),
+                _ => {},
+            }
+            state[index] = 1;
+            for input in shader.nodes[index].inputs() {
+                if let Input::Node(next) = input {
+                    visit(shader, *next, state)?;
+                }
+            }
+            state[index] = 2;
+            Ok(())
+        }
+        let mut state = vec![0; self.nodes.len()];
+        visit(self, self.output, &mut state)
+    }
+
+    pub fn evaluate(&self, sp: &ShadingPoint) -> Color {
+        self.node(self.output, sp)
+    }
+
+    // the perturbed world space normal for a normal map slot, replacing the given normal. tangent space
+    // maps go through the tangent frame, a triplanar normal map carries its own frames
+    pub fn normal(&self, sp: &ShadingPoint, tangent: Vec3, bitangent: Vec3, normal: Vec3, strength: f64) -> Vec3 {
+        if let Node::Triplanar { input, placement, sharpness, normal_map: true } = &self.nodes[self.output] {
+            return self.triplanar_normal(input, placement, *sharpness, sp, normal, strength)
+        }
+        self.evaluate(sp).to_normal_vec(tangent, bitangent, normal, strength)
+    }
+
+    // dh/du and dh/dv of the red channel by forward differences across the filter footprint, so the
+    // differenced lookups are as filtered as the shading ones. the position moves along dpdu and dpdv
+    // with the uv, which lets projected and procedural heights differentiate as well
+    pub fn height_derivatives(&self, sp: &ShadingPoint, dpdu: Vec3, dpdv: Vec3) -> (f64, f64) {
+        let (major, minor) = sp.footprint;
+        let step = |a: f32, b: f32| {
+            let d = 0.5 * (a.abs() + b.abs()) as f64;
+            if d > 0.0 {d} else {0.0005}
+        };
+        let (du, dv) = (step(major.x, minor.x), step(major.y, minor.y));
+        let to_object = sp.object_to_world.inverse().unwrap_or(Mat3::identity());
+        let at = |du: f64, dv: f64| {
+            let offset = dpdu * du + dpdv * dv;
+            let mut shifted = *sp;
+            shifted.uv = Vec2::new(sp.uv.x + du as f32, sp.uv.y + dv as f32);
+            shifted.point = sp.point + offset;
+            shifted.object_point = sp.object_point + to_object * offset;
+            self.evaluate(&shifted).r
+        };
+        let center = at(0.0, 0.0);
+        ((at(du, 0.0) - center) / du, (at(0.0, dv) - center) / dv)
+    }
+
+    fn ramp(&self, stops: &[(f64, Input)], interpolation: Interpolation, position: f64, sp: &ShadingPoint) -> Color {
+        let Some((first, _)) = stops.first() else {
+            return Color::black()
+        };
+        if position <= *first {
+            return self.input(&stops[0].1, sp)
+        }
+        for pair in stops.windows(2) {
+            let ((p0, c0), (p1, c1)) = (&pair[0], &pair[1]);
+            if position < *p1 {
+                let t = if p1 > p0 {(position - p0) / (p1 - p0)} else {1.0};
+                let t = match interpolation {
+                    Interpolation::Step => 0.0,
+                    Interpolation::Linear => t,
+                    Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+                };
+                let (c0, c1) = (self.input(c0, sp), self.input(c1, sp));
+                return Color::new(
+                    c0.r + (c1.r - c0.r) * t,
+                    c0.g + (c1.g - c0.g) * t,
+                    c0.b + (c1.b - c0.b) * t,
+                    1.0
+                )
+            }
+        }
+        self.input(&stops[stops.len() - 1].1, sp)
+    }
+
+    // the input read through the projection along each axis and its blend weight, zero weights are skipped.
+    // each projection's frame is right handed about the side of the axis the normal faces, so nothing mirrors
+    fn triplanar(&self, input: &Input, placement: &Placement, sharpness: f64, sp: &ShadingPoint) -> Vec<(Color, f64, [Vec3; 3])> {
+        let (normal, to_space) = match placement.space {
+            Space::Object => {
+                let to_object = sp.object_to_world.inverse().unwrap_or(Mat3::identity());
+                ((sp.object_to_world.transpose() * sp.normal).normalize(), to_object)
+            },
+            _ => (sp.normal, Mat3::identity()),
+        };
+        let p = placement.point(sp);
+        let axes = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        let weights = [normal.x, normal.y, normal.z].map(|n| n.abs().powf(sharpness));
+        let total: f64 = weights.iter().sum();
+
+        let mut projections = Vec::new();
+        for (i, axis) in axes.iter().enumerate() {
+            let weight = weights[i] / total.max(1e-12);
+            if weight < 1e-4 {
+                continue
+            }
+            let sign = if axis.dot(&normal) < 0.0 {-1.0} else {1.0};
+            let (tangent, bitangent) = match i {
+                0 => (axes[2] * -sign, axes[1]),
+                1 => (axes[0], axes[2] * -sign),
+                _ => (axes[0] * sign, axes[1]),
+            };
+            let project = |d: Vec3| Vec2::new(d.dot(&tangent) as f32, d.dot(&bitangent) as f32);
+            let mut projected = *sp;
+            projected.uv = project(p);
+            projected.footprint = (
+                project(to_space * sp.footprint_world.0 * placement.frequency),
+                project(to_space * sp.footprint_world.1 * placement.frequency),
+            );
+            projections.push((self.input(input, &projected), weight, [tangent, bitangent, *axis * sign]));
+        }
+        projections
+    }
+
+    // per projection tangent space offsets added onto the surface normal and blended, then back to world space
+    fn triplanar_normal(&self, input: &Input, placement: &Placement, sharpness: f64, sp: &ShadingPoint, normal: Vec3, strength: f64) -> Vec3 {
+        let object = placement.space == Space::Object;
+        let normal = if object {(sp.object_to_world.transpose() * normal).normalize()} else {normal};
+        let mut n = normal;
+        for (c, weight, [tangent, bitangent, _]) in self.triplanar(input, placement, sharpness, sp) {
+            n = n + (tangent * (c.r * 2.0 - 1.0) + bitangent * (c.g * 2.0 - 1.0)) * (weight * strength);
+        }
+        if object {
+            if let Some(to_object) = sp.object_to_world.inverse() {
+                n = to_object.transpose() * n;
+            }
+        }
+        n.normalize()
+    }
+
+    fn input(&self, input: &Input, sp: &ShadingPoint) -> Color {
+        match input {
+            Input::Constant(c) => *c,
+            Input::Node(index) => self.node(*index, sp),
+        }
+    }
+
+    fn node(&self, index: usize, sp: &ShadingPoint) -> Color {
+        match &self.nodes[index] {
+            Node::Constant(c) => *c,
+            Node::Texture { map, repeat, offset, rotate } => {
+                let (u, v) = transform_uv(sp.uv, *repeat, *offset, *rotate);
+                let axis = |a: Vec2| {
+                    let (du, dv) = transform_vector(a, *repeat, *rotate);
+                    Vec2::new(du as f32, dv as f32)
+                };
+                map.lookup(u, v, axis(sp.footprint.0), axis(sp.footprint.1))
+            },
+            Node::Math { op, a, b } => {
+                let (a, b) = (self.input(a, sp), self.input(b, sp));
+                let f = |a: f64, b: f64| match op {
+                    MathOp::Add => a + b,
+                    MathOp::Subtract => a - b,
+                    MathOp::Multiply => a * b,
+                    MathOp::Divide => if b != 0.0 {a / b} else {0.0},
+                    MathOp::Power => a.max(0.0).powf(b),
+                    MathOp::Minimum => a.min(b),
+                    MathOp::Maximum => a.max(b),
+                    MathOp::Invert => 1.0 - a,
+                };
+                Color::new(f(a.r, b.r), f(a.g, b.g), f(a.b, b.b), 1.0)
+            },
+            Node::ColorCorrect { input, hue, saturation, gamma, gain } => {
+                let c = self.input(input, sp);
+                let (h, s, v) = rgb_to_hsv(c);
+                let c = hsv_to_rgb((h + hue / 360.0).rem_euclid(1.0), (s * saturation).max(0.0), v);
+                let g = |x: f64| x.max(0.0).powf(1.0 / gamma.max(1e-4)) * gain;
+                Color::new(g(c.r), g(c.g), g(c.b), 1.0)
+            },
+            Node::Ramp { input, along_u, stops, interpolation } => {
+                let position = match input {
+                    Some(input) => self.input(input, sp).r,
+                    None if *along_u => sp.uv.x as f64,
+                    None => sp.uv.y as f64,
+                };
+                self.ramp(stops, *interpolation, position, sp)
+            },
+            Node::Mix { a, b, mask } => {
+                let (a, b, mask) = (self.input(a, sp), self.input(b, sp), self.input(mask, sp));
+                Color::new(
+                    a.r + (b.r - a.r) * mask.r,
+                    a.g + (b.g - a.g) * mask.g,
+                    a.b + (b.b - a.b) * mask.b,
+                    1.0
+                )
+            },
+            Node::Noise { placement } => Color::white() * (0.5 + 0.5 * perlin(placement.point(sp))).clamp(0.0, 1.0),
+            Node::Fbm { placement, basis, octaves, lacunarity, gain, turbulence: true } => {
+                Color::white() * turbulence(placement.point(sp), *basis, *octaves, *lacunarity, *gain).clamp(0.0, 1.0)
+            },
+            Node::Fbm { placement, basis, octaves, lacunarity, gain, turbulence: false } => {
+                Color::white() * (0.5 + 0.5 * fbm(placement.point(sp), *basis, *octaves, *lacunarity, *gain)).clamp(0.0, 1.0)
+            },
+            Node::Voronoi { placement, jitter, output } => {
+                let (f1, f2, cell) = worley(placement.point(sp), *jitter, placement.space == Space::Uv);
+                match output {
+                    VoronoiOutput::F1 => Color::white() * f1,
+                    VoronoiOutput::F2 => Color::white() * f2,
+                    VoronoiOutput::Edge => Color::white() * (f2 - f1),
+                    VoronoiOutput::Cell => Color::new(hash_float(cell, 3), hash_float(cell, 4), hash_float(cell, 5), 1.0),
+                }
+            },
+            Node::Checker { placement, a, b } => {
+                let p = placement.point(sp);
+                let parity = (p.x.floor() + p.y.floor() + p.z.floor()) as i64;
+                if parity.rem_euclid(2) == 0 {self.input(a, sp)} else {self.input(b, sp)}
+            },
+            Node::Bricks { placement, size, mortar_width, row_offset, brick, mortar } => {
+                let p = placement.point(sp);
+                let row = (p.y / size[1]).floor();
+                let x = p.x + if row.rem_euclid(2.0) == 1.0 {row_offset * size[0]} else {0.0};
+                let (bx, by) = (x.rem_euclid(size[0]), p.y.rem_euclid(size[1]));
+                if bx < *mortar_width || by < *mortar_width {self.input(mortar, sp)} else {self.input(brick, sp)}
+            },
+            Node::Gradient { placement, kind } => {
+                let p = placement.point(sp);
+                let value = match kind {
+                    GradientKind::Linear => p.x,
+                    GradientKind::Radial => p.y.atan2(p.x) / (2.0 * std::f64::consts::PI) + 0.5,
+                    GradientKind::Spherical => 1.0 - p.length(),
+                };
+                Color::white() * value.clamp(0.0, 1.0)
+            },
+            Node::Triplanar { input, placement, sharpness, normal_map: false } => {
+                let mut sum = Color::new(0.0, 0.0, 0.0, 1.0);
+                for (c, weight, _) in self.triplanar(input, placement, *sharpness, sp) {
+                    sum = Color::new(sum.r + c.r * weight, sum.g + c.g * weight, sum.b + c.b * weight, 1.0);
+                }
+                sum
+            },
+            // read as a color the blended normal is encoded like a normal map
+            Node::Triplanar { input, placement, sharpness, normal_map: true } => {
+                let n = self.triplanar_normal(input, placement, *sharpness, sp, sp.normal, 1.0);
+                Color::new(n.x * 0.5 + 0.5, n.y * 0.5 + 0.5, n.z * 0.5 + 0.5, 1.0)
+            },
+            Node::Fresnel { ior } => Color::white() * fresnel_dielectric(sp.normal.dot(&sp.view).abs(), *ior),
+            Node::FacingRatio => Color::white() * sp.normal.dot(&sp.view).abs(),
+        }
+    }
+}
+
+impl Node {
+    fn inputs(&self) -> Vec<&Input> {
+        match self {
+            Node::Math { a, b, .. } => vec![a, b],
+            Node::ColorCorrect { input, .. } => vec![input],
+            Node::Ramp { input, stops, .. } => input.iter().chain(stops.iter().map(|(_, color)| color)).collect(),
+            Node::Mix { a, b, mask } => vec![a, b, mask],
+            Node::Checker { a, b, .. } => vec![a, b],
+            Node::Bricks { brick, mortar, .. } => vec![brick, mortar],
+            Node::Triplanar { input, .. } => vec![input],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn transform_vector(d: Vec2, repeat: [f64; 2], rotate: f64) -> (f64, f64) {
+    let (sin, cos) = rotate.to_radians().sin_cos();
+    let (u, v) = (d.x as f64, d.y as f64);
+    ((cos * u - sin * v) * repeat[0], (sin * u + cos * v) * repeat[1])
+}
+
+fn transform_uv(uv: Vec2, repeat: [f64; 2], offset: [f64; 2], rotate: f64) -> (f64, f64) {
+    let (u, v) = transform_vector(Vec2::new(uv.x - 0.5, uv.y - 0.5), repeat, rotate);
+    (u + 0.5 * repeat[0] + offset[0], v + 0.5 * repeat[1] + offset[1])
+}
+
+fn rgb_to_hsv(c: Color) -> (f64, f64, f64) {
+    let max = c.r.max(c.g).max(c.b);
+    let min = c.r.min(c.g).min(c.b);
+    let delta = max - min;
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == c.r {
+        ((c.g - c.b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == c.g {
+        ((c.b - c.r) / delta + 2.0) / 6.0
+    } else {
+        ((c.r - c.g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max > 0.0 {delta / max} else {0.0};
+    (hue, saturation, max)
+}
+
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> Color {
+    let f = |n: f64| {
+        let k = (n + h * 6.0).rem_euclid(6.0);
+        v - v * s * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    Color::new(f(5.0), f(3.0), f(1.0), 1.0)
+}
+
+fn parse_input(value: &Value, names: &HashMap<&str, usize>) -> Result<Input, String> {
+    match value {
+        Value::Number(n) => Ok(Input::Constant(Color::white() * n.as_f64().unwrap_or(0.0))),
+        Value::Array(c) if c.len() >= 3 => Ok(Input::Constant(Color::new(
+            c[0].as_f64().unwrap_or(0.0),
+            c[1].as_f64().unwrap_or(0.0),
+            c[2].as_f64().unwrap_or(0.0),
+            1.0
+        ))),
+        Value::String(name) => names.get(name.as_str())
+            .map(|i| Input::Node(*i))
+            .ok_or(format!("unknown node {name}")),
+        _ => Err(format!("bad input {value}")),
+    }
+}
+
+fn parse_node(node: &Value, names: &HashMap<&str, usize>) -> Result<Node, String> {
+    let input = |key: &str, default: f64| match &node[key] {
+        Value::Null => Ok(Input::Constant(Color::white() * default)),
+        value => parse_input(value, names),
+    };
+    let number = |key: &str, default: f64| node[key].as_f64().unwrap_or(default);
+    let pair = |key: &str, default: f64| match node[key].as_array() {
+        Some(p) if p.len() >= 2 => [p[0].as_f64().unwrap_or(default), p[1].as_f64().unwrap_or(default)],
+        _ => [default, default],
+    };
+    // procedurals share "space" (uv, world or object), "frequency" and an [x, y, z] "offset"
+    let placement_in = |default_space: &str| -> Result<Placement, String> {
+        let space = match node["space"].as_str().unwrap_or(default_space) {
+            "uv" => Space::Uv,
+            "world" => Space::World,
+            "object" => Space::Object,
+            space => return Err(format!("unknown space {space}")),
+        };
+        let offset = match node["offset"].as_array() {
+            Some(o) if o.len() >= 3 => Vec3::new(o[0].as_f64().unwrap_or(0.0), o[1].as_f64().unwrap_or(0.0), o[2].as_f64().unwrap_or(0.0)),
+            _ => Vec3::zeros(),
+        };
+        Ok(Placement { space, frequency: number("frequency", 1.0), offset })
+    };
+    let placement = || placement_in("uv");
+
+    match node["type"].as_str().unwrap_or("") {
+        "constant" => match input("value", 0.0)? {
+            Input::Constant(c) => Ok(Node::Constant(c)),
+            Input::Node(_) => Err("constant value must be a number or color".to_string()),
+        },
+        "texture" => {
+            let path = node["path"].as_str().ok_or("texture has no path")?;
+            let srgb = node["srgb"].as_bool().unwrap_or(true);
+            let mut map = TextureMap::new(path, srgb)?;
+            map.filter = match node["filter"].as_str().unwrap_or("bilinear") {
+                "nearest" => Filter::Nearest,
+                "bilinear" => Filter::Bilinear,
+                "bicubic" => Filter::Bicubic,
+                filter => return Err(format!("unknown filter {filter}")),
+            };
+            map.wrap = match node["wrap"].as_str().unwrap_or("repeat") {
+                "repeat" => Wrap::Repeat,
+                "clamp" => Wrap::Clamp,
+                "mirror" => Wrap::Mirror,
+                wrap => return Err(format!("unknown wrap mode {wrap}")),
+            };
+            map.mip_filter = match node["mipmap"].as_str().unwrap_or("trilinear") {
+                "none" => MipFilter::None,
+                "trilinear" => MipFilter::Trilinear,
+                "anisotropic" => MipFilter::Anisotropic,
+                mipmap => return Err(format!("unknown mipmap filter {mipmap}")),
+            };
+            Ok(Node::Texture {
+                map: Arc::new(map),
+                repeat: pair("repeat", 1.0),
+                offset: pair("offset", 0.0),
+                rotate: number("rotate", 0.0),
+            })
+        },
+        "math" => {
+            let op = match node["op"].as_str().unwrap_or("add") {
+                "add" => MathOp::Add,
+                "subtract" => MathOp::Subtract,
+                "multiply" => MathOp::Multiply,
+                "divide" => MathOp::Divide,
+                "power" => MathOp::Power,
+                "minimum" => MathOp::Minimum,
+                "maximum" => MathOp::Maximum,
+                "invert" => MathOp::Invert,
+                op => return Err(format!("unknown math op {op}")),
+            };
+            Ok(Node::Math { op, a: input("a", 0.0)?, b: input("b", 0.0)? })
+        },
+        "color_correct" => Ok(Node::ColorCorrect {
+            input: input("input", 0.0)?,
+            hue: number("hue", 0.0),
+            saturation: number("saturation", 1.0),
+            gamma: number("gamma", 1.0),
+            gain: number("gain", 1.0),
+        }),
+        "ramp" => {
+            let mut stops = Vec::new();
+            for stop in node["stops"].as_array().ok_or("ramp has no stops")? {
+                stops.push((stop["position"].as_f64().unwrap_or(0.0), parse_input(&stop["color"], names)?));
+            }
+            stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let interpolation = match node["interpolation"].as_str().unwrap_or("linear") {
+                "step" | "none" => Interpolation::Step,
+                "smooth" => Interpolation::Smooth,
+                _ => Interpolation::Linear,
+            };
+            let input = if node["input"].is_null() {None} else {Some(input("input", 0.0)?)};
+            let along_u = node["axis"].as_str() == Some("u");
+            Ok(Node::Ramp { input, along_u, stops, interpolation })
+        },
+        "mix" => Ok(Node::Mix { a: input("a", 0.0)?, b: input("b", 1.0)?, mask: input("mask", 0.5)? }),
+        "noise" => Ok(Node::Noise { placement: placement()? }),
+        kind @ ("fbm" | "turbulence") => Ok(Node::Fbm {
+            placement: placement()?,
+            basis: match node["basis"].as_str().unwrap_or("perlin") {
+                "perlin" => perlin,
+                "simplex" => simplex,
+                basis => return Err(format!("unknown noise basis {basis}")),
+            },
+            octaves: number("octaves", 4.0).max(1.0) as u32,
+            lacunarity: number("lacunarity", 2.0),
+            gain: number("gain", 0.5),
+            turbulence: kind == "turbulence",
+        }),
+        "voronoi" => Ok(Node::Voronoi {
+            placement: placement()?,
+            jitter: number("jitter", 1.0).clamp(0.0, 1.0),
+            output: match node["output"].as_str().unwrap_or("f1") {
+                "f1" => VoronoiOutput::F1,
+                "f2" => VoronoiOutput::F2,
+                "edge" => VoronoiOutput::Edge,
+                "cell" => VoronoiOutput::Cell,
+                output => return Err(format!("unknown voronoi output {output}")),
+            },
+        }),
+        "checker" => Ok(Node::Checker { placement: placement()?, a: input("a", 0.0)?, b: input("b", 1.0)? }),
+        "bricks" => {
+            let size = match node["size"].as_array() {
+                Some(s) if s.len() >= 2 => [s[0].as_f64().unwrap_or(1.0), s[1].as_f64().unwrap_or(0.5)],
+                _ => [1.0, 0.5],
+            };
+            Ok(Node::Bricks {
+                placement: placement()?,
+                size,
+                mortar_width: number("mortar_width", 0.05),
+                row_offset: number("row_offset", 0.5),
+                brick: input("brick", 0.6)?,
+                mortar: input("mortar", 0.2)?,
+            })
+        },
+        "gradient" => Ok(Node::Gradient {
+            placement: placement()?,
+            kind: match node["kind"].as_str().unwrap_or("linear") {
+                "linear" => GradientKind::Linear,
+                "radial" => GradientKind::Radial,
+                "spherical" => GradientKind::Spherical,
+                kind => return Err(format!("unknown gradient kind {kind}")),
+            },
+        }),
+        kind @ ("triplanar" | "triplanar_normal") => {
+            let placement = placement_in("world")?;
+            if placement.space == Space::Uv {
+                return Err("triplanar projects in world or object space".to_string())
+            }
+            Ok(Node::Triplanar {
+                input: input("input", 0.0)?,
+                placement,
+                sharpness: number("sharpness", 4.0).max(0.0),
+                normal_map: kind == "triplanar_normal",
+            })
+        },
+        "fresnel" => Ok(Node::Fresnel { ior: number("ior", 1.5) }),
+        "facing_ratio" => Ok(Node::FacingRatio),
+        kind => Err(format!("unknown node type {kind}")),
+    }
+}
@@ -1,11 +1,14 @@
 
 use crate::vec3::Vec3;
 use crate::vec2::Vec2;
+use crate::mat3::Mat3;
 use crate::hit::Hittable;
 use crate::hit::HitRecord;
 use crate::ray::Ray;
 use crate::material::Material;
+use crate::shader::ShadingPoint;
 use crate::aabb::Aabb;
+use crate::utility::clamp;
 use std::sync::Arc;
 use std::f64::consts::PI;
 
@@ -19,6 +22,7 @@ pub struct Sphere {
     pub radius: f64,
     pub area: f64,
     pub material: Arc<Material>,
+    pub object_id: usize,
 }
 
 impl Sphere {
@@ -32,6 +36,7 @@ impl Sphere {
             radius,
             area,
             material,
+            object_id: 0,
         }
     }
 
@@ -55,19 +60,41 @@ impl Sphere {
                     let normal = ((p - self.center(r.time)) / self.radius).normalize();
                     let front_face = r.direction.dot(&normal) < 0.0;
 
-                    let theta = f64::acos(-p.y);
-                    let phi = f64::atan2(-p.z, p.x) + PI;        
+                    // spherical uvs from the unit normal, dP/du runs around y and dP/dv pole to pole
+                    let theta = f64::acos(clamp(-normal.y, -1.0, 1.0));
+                    let phi = f64::atan2(-normal.z, normal.x) + PI;        
                     let u = phi / (2.0*PI);
                     let v = theta / PI;
-                    return (true,
-                        Some(HitRecord {
+                    let uv = Vec2::new(u as f32, v as f32);
+                    let local = p - self.center(r.time);
+                    let dpdu = Vec3::new(local.z, 0.0, -local.x) * (2.0 * PI);
+                    let sin_theta = f64::max(theta.sin(), 1e-6);
+                    let dpdv = Vec3::new(-local.x * normal.y / sin_theta, self.radius * sin_theta, -local.z * normal.y / sin_theta) * PI;
+                    let tangent = if dpdu.length_squared() > 1e-12 {dpdu.normalize()} else {normal.tangent_bitangent().0};
+                    let bitangent = normal.cross(&tangent);
+                    let bitangent = if bitangent.dot(&dpdv) < 0.0 {-bitangent} else {bitangent};
+                    let flip = if front_face {1.0} else {-1.0};
+                    let rec = HitRecord {
                         t: *root,
                         point: p,
-                        normal: if front_face {normal} else {-normal},
-                        uv: Vec2::new(u as f32, v as f32),
+                        object_point: p - self.center(r.time),
+                        object_to_world: Mat3::identity(),
+                        normal: normal * flip,
+                        tangent: tangent * flip,
+                        bitangent: bitangent * flip,
+                        dpdu,
+                        dpdv,
+                        dndu: dpdu / self.radius,
+                        dndv: dpdv / self.radius,
+                        uv,
                         front_face,
                         material: self.material.clone(), 
-                    }));
+                        object_id: self.object_id,
+                    };
+                    if !self.material.opaque() && self.material.cutout(&ShadingPoint::new(&rec, r)) {
+                        continue;
+                    }
+                    return (true, Some(rec));
                 }
             }
         }
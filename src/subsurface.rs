@@ -0,0 +1,157 @@
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::hit::{HitRecord, Object};
+use crate::pdf::{Pdf, CosinePdf, HenyeyGreensteinPdf};
+use crate::onb::Onb;
+use crate::utility::{random_float, random_int, clamp, INF};
+use std::f64::consts::PI;
+
+
+const MAX_STEPS: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubsurfaceMode {
+    RandomWalk,
+    Diffusion,
+}
+
+// volumetric random walk below the surface, the walk starts on the entry ray and ends
+// when it crosses the boundary of the same object again, returning the exit ray and the path throughput
+pub fn random_walk(entry: &Ray, world: &Object, object_id: usize, color: Color, radius: Color, anisotropy: f64) -> Option<(Ray, Color)> {
+    let (sigma_t, albedo) = scattering_coefficients(color, radius);
+    let mut throughput = [1.0, 1.0, 1.0];
+    let mut ray = Ray::new(entry.origin, entry.direction.normalize(), entry.time);
+
+    for step in 0..MAX_STEPS {
+        // pick the channel that drives the free flight proportionally to throughput
+        let sum = throughput[0] + throughput[1] + throughput[2];
+        if sum <= 0.0 {
+            return None
+        }
+        let pick = [throughput[0] / sum, throughput[1] / sum, throughput[2] / sum];
+        let roll = random_float();
+        let channel = if roll < pick[0] {0} else if roll < pick[0] + pick[1] {1} else {2};
+        let distance = -(1.0 - random_float()).ln() / sigma_t[channel];
+
+        // the boundary is the first hit on the entry object, other geometry doesn't stop the walk
+        let (_, hit) = world.hit_filtered(&ray, 0.0001, INF, object_id);
+        let boundary = hit.as_ref().map(|h| h.t).unwrap_or(INF);
+
+        if distance >= boundary {
+            // left the medium, weight by transmittance over the spectral mis pdf
+            let boundary_hit = hit.unwrap();
+            let transmittance = sigma_t.map(|s| (-s * boundary).exp());
+            let pdf: f64 = (0..3).map(|c| pick[c] * transmittance[c]).sum();
+            for c in 0..3 {
+                throughput[c] *= transmittance[c] / pdf;
+            }
+            let exit = Ray::new(boundary_hit.point, ray.direction, ray.time);
+            return Some((exit, Color::new(throughput[0], throughput[1], throughput[2], 1.0)))
+        }
+
+        // scatter inside the medium
+        let transmittance = sigma_t.map(|s| (-s * distance).exp());
+        let pdf: f64 = (0..3).map(|c| pick[c] * sigma_t[c] * transmittance[c]).sum();
+        for c in 0..3 {
+            throughput[c] *= albedo[c] * sigma_t[c] * transmittance[c] / pdf;
+        }
+
+        // russian roulette once the walk is long
+        if step > 8 {
+            let survive = clamp(throughput[0].max(throughput[1]).max(throughput[2]), 0.05, 1.0);
+            if random_float() > survive {
+                return None
+            }
+            throughput = throughput.map(|t| t / survive);
+        }
+
+        let phase = HenyeyGreensteinPdf::new(ray.direction, anisotropy);
+        ray = Ray::new(ray.at(distance), phase.generate(), ray.time);
+    }
+    None
+}
+
+// invert the multiple scattering albedo into single scattering albedo and extinction,
+// van de hulst's relation with the radius scaling from chiang et al. 2016
+fn scattering_coefficients(color: Color, radius: Color) -> ([f64; 3], [f64; 3]) {
+    let mut sigma_t = [0.0; 3];
+    let mut albedo = [0.0; 3];
+    for (c, (a, r)) in [(color.r, radius.r), (color.g, radius.g), (color.b, radius.b)].into_iter().enumerate() {
+        let a = clamp(a, 0.0, 0.999);
+        let r = f64::max(r, 1e-4);
+        let s = 1.9 - a + 3.5 * (a - 0.8) * (a - 0.8);
+        sigma_t[c] = 1.0 / (r * s);
+        let x = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+        albedo[c] = 1.0 - x * x;
+    }
+    (sigma_t, albedo)
+}
+
+// christensen-burley normalized diffusion, a probe along a random axis finds where light
+// leaves the same object and the profile weights that exit point
+pub fn diffusion_probe(rec: &HitRecord, world: &Object, color: Color, radius: Color, time: f64) -> Option<(Ray, Color)> {
+    let albedo = [color.r, color.g, color.b].map(|a| clamp(a, 0.0, 0.999));
+    let radius = [radius.r, radius.g, radius.b].map(|r| f64::max(r, 1e-4));
+    let d: [f64; 3] = std::array::from_fn(|c| radius[c] / burley_scale(albedo[c]));
+    let r_max = 3.0 * d.iter().cloned().fold(0.0, f64::max) * 1000.0_f64.ln();
+
+    // pick a channel and a projection axis, the normal axis is favoured
+    let channel = (random_int(0.0, 2.0) as usize).min(2);
+    let mut frame = Onb::new();
+    frame.build_from_w_tangent(rec.normal, rec.tangent);
+    let axes = [frame.w(), frame.u(), frame.v()];
+    let axis_prob = [0.5, 0.25, 0.25];
+    let roll = random_float();
+    let axis = if roll < 0.5 {0} else if roll < 0.75 {1} else {2};
+    let (w, u, v) = (axes[axis], axes[(axis + 1) % 3], axes[(axis + 2) % 3]);
+
+    // the profile is a mix of two exponentials, a quarter of the energy in the short one
+    let scale = if random_float() < 0.25 {d[channel]} else {3.0 * d[channel]};
+    let r = -scale * (1.0 - random_float()).ln();
+    if r >= r_max {
+        return None
+    }
+    let phi = 2.0 * PI * random_float();
+    let half_length = (r_max * r_max - r * r).sqrt();
+    let origin = rec.point + (u * phi.cos() + v * phi.sin()) * r + w * half_length;
+    let probe = Ray::new(origin, -w, time);
+    let hits = HitRecord::hit_object(world, &probe, 0.0, 2.0 * half_length, rec.object_id);
+    if hits.is_empty() {
+        return None
+    }
+    let count = hits.len();
+    let hit = &hits[(random_int(0.0, count as f64 - 1.0) as usize).min(count - 1)];
+    let outward = if hit.front_face {hit.normal} else {-hit.normal};
+
+    // the same point could have come from any channel and axis
+    let delta = hit.point - rec.point;
+    let mut pdf = 0.0;
+    for (a, axis_dir) in axes.iter().enumerate() {
+        let r_projected = (delta - *axis_dir * delta.dot(axis_dir)).length();
+        let cosine = outward.dot(axis_dir).abs();
+        for scale in d {
+            pdf += burley_profile(r_projected, scale) * cosine * axis_prob[a] / 3.0;
+        }
+    }
+    pdf /= count as f64;
+    if pdf <= 0.0 {
+        return None
+    }
+    let distance = delta.length();
+    let weight: [f64; 3] = std::array::from_fn(|c| albedo[c] * burley_profile(distance, d[c]) / pdf);
+
+    // leave diffusely through the probed point
+    let exit = Ray::new(hit.point, CosinePdf::new(outward).generate(), time);
+    Some((exit, Color::new(weight[0], weight[1], weight[2], 1.0)))
+}
+
+// burley's fit of the shape parameter for a searchlight configuration
+fn burley_scale(albedo: f64) -> f64 {
+    1.85 - albedo + 7.0 * (albedo - 0.8).abs().powi(3)
+}
+
+// radial profile normalized over the plane
+fn burley_profile(r: f64, d: f64) -> f64 {
+    let r = f64::max(r, 1e-6);
+    ((-r / d).exp() + (-r / (3.0 * d)).exp()) / (8.0 * PI * d * r)
+}
@@ -1,102 +1,245 @@
-use image::{DynamicImage, GenericImageView, Pixel, ImageBuffer, Rgb};
-use image::codecs::hdr::{HdrDecoder, Rgbe8Pixel};
 use crate::color::Color;
-use palette::{Srgb, LinSrgb};
+use crate::vec2::Vec2;
+use crate::texture_cache::{texture_cache, CachedTexture, TexelReader};
+use std::collections::HashMap;
 use std::path::Path;
-use rayon::prelude::*;
-use crate::vec3::Vec3;
-use crate::mat3::Mat3;
+use std::sync::Arc;
 
 
+// reconstruction within a single mip level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+// how a lookup footprint is filtered across mip levels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MipFilter {
+    None,
+    Trilinear,
+    Anisotropic,
+}
+
+// most probes an anisotropic lookup takes along the footprint's long axis
+const MAX_ANISOTROPY: f64 = 16.0;
+
+// a udim set keys its tiles by number, 1001 + u + 10 v for the integer part of the uv
+#[derive(Debug, Clone)]
+pub enum Source {
+    Image(Arc<CachedTexture>),
+    Udim(Arc<HashMap<u32, Arc<CachedTexture>>>),
+}
+
+// texels are stored linear in the shared texture cache, clones share the same data
 #[derive(Debug, Clone)]
 pub struct TextureMap {
-    pub image: ImageBuffer<Rgb<f32>, Vec<f32>>,
-    srgb: bool
+    pub source: Source,
+    pub filter: Filter,
+    pub wrap: Wrap,
+    pub mip_filter: MipFilter,
 }
 
 impl TextureMap {
-    pub fn new(file_path: &str, srgb: bool) -> Self {    
-        if srgb {
-            let image =image::open(file_path).unwrap().into_rgb32f();
-            TextureMap{image, srgb}
-        } else{
-            let ext = Path::new(file_path).extension().unwrap();
-            if ext == "hdr" {                
-                let file = std::fs::File::open(file_path).unwrap();
-                let reader = std::io::BufReader::new(file);
-                let decoder = HdrDecoder::new(reader).unwrap();
-                let metadata = decoder.metadata();
-                let pixels = decoder.read_image_hdr().unwrap();
-                let buffer_data = pixels.par_chunks(1000)
-                    .flat_map(|chunk| {
-                        let mut buffer_data = Vec::new();
-                        for pixel in chunk {
-                            buffer_data.push(pixel[0]);
-                            buffer_data.push(pixel[1]);
-                            buffer_data.push(pixel[2]);
-                        }
-                        buffer_data
-                    })
-                .collect::<Vec<_>>();
-                let buffer = ImageBuffer::from_raw(metadata.width, metadata.height, buffer_data).unwrap();
-                TextureMap {image: buffer, srgb}
-            } else {
-                let image =image::open(file_path).unwrap().into_rgb32f();
-                TextureMap{image, srgb}
-            }
+    // a <UDIM> token in the file name loads every matching tile next to it. only headers are
+    // read here, so a missing or unreadable file is an error before rendering starts
+    pub fn new(file_path: &str, srgb: bool) -> Result<Self, String> {
+        let source = if file_path.contains("<UDIM>") {
+            Source::Udim(Arc::new(udim_tiles(file_path, srgb)?))
+        } else {
+            Source::Image(texture_cache().get(file_path, srgb)?)
+        };
+        Ok(TextureMap {
+            source,
+            filter: Filter::Bilinear,
+            wrap: Wrap::Repeat,
+            mip_filter: MipFilter::Trilinear,
+        })
+    }
 
+    // the image under a uv with the uv local to it. udim tiles clamp at their edges
+    // and a uv over a missing tile has nothing to sample
+    fn resolve(&self, u: f64, v: f64) -> Option<(&CachedTexture, Wrap, f64, f64)> {
+        match &self.source {
+            Source::Image(texture) => Some((texture, self.wrap, u, v)),
+            Source::Udim(tiles) => {
+                let (tile_u, tile_v) = (u.floor(), v.floor());
+                if !(0.0..10.0).contains(&tile_u) || tile_v < 0.0 {
+                    return None
+                }
+                let texture = tiles.get(&(1001 + tile_u as u32 + 10 * tile_v as u32))?;
+                Some((texture, Wrap::Clamp, u - tile_u, v - tile_v))
+            },
         }
     }
-    
-    pub fn sample(&self, u: f32, v: f32) -> Color {
-        let (width, height) = self.image.dimensions();
-        let x = (u * width as f32) as u32 % width;
-        let y = ((1.0-v) * height as f32) as u32 % height;
-        let pixel = self.image.get_pixel(x, y);
-        if self.srgb {
-            let srgb = Srgb::new(
-            f32::from(pixel[0]),
-            f32::from(pixel[1]),
-            f32::from(pixel[2]),
-            ).into_linear();
-            Color::new(srgb.red.into(), srgb.green.into(), srgb.blue.into(), 1.0)
-        } else {
-            Color::new(pixel[0].into(), pixel[1].into(), pixel[2].into(), 1.0)
-        }
 
+    fn wrap_index(wrap: Wrap, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let i = match wrap {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {m} else {2 * n - 1 - m}
+            },
+        };
+        i as u32
     }
 
+    fn texel(reader: &mut TexelReader, wrap: Wrap, (width, height): (u32, u32), x: i64, y: i64) -> [f64; 3] {
+        let pixel = reader.texel(Self::wrap_index(wrap, x, width), Self::wrap_index(wrap, y, height));
+        [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]
+    }
 
-    pub fn get_gradient(&self, u: f32, v: f32) -> Color {
-        let (width, height) = self.image.dimensions();
-        let x = (u * width as f32) as u32 % width;
-        let y = ((1.0-v) * height as f32) as u32 % height;
+    // v runs up the image, texel centers sit at half integers
+    fn sample_level(&self, texture: &CachedTexture, wrap: Wrap, level: usize, u: f64, v: f64) -> Color {
+        let size = texture.dimensions(level);
+        let (x, y) = (u * size.0 as f64, (1.0 - v) * size.1 as f64);
 
-        let x_next = if x + 1 > width-1 {x} else {x+1};
-        let y_next = if y + 1 > height-1 {y} else {y+1};
+        // one reader per lookup, neighbouring texels mostly share its tile
+        let mut reader = texture.reader(level);
+        let mut sum = [0.0; 3];
+        let mut add = |tx: i64, ty: i64, weight: f64| {
+            let t = Self::texel(&mut reader, wrap, size, tx, ty);
+            for c in 0..3 {
+                sum[c] += t[c] * weight;
+            }
+        };
+        match self.filter {
+            Filter::Nearest => add(x.floor() as i64, y.floor() as i64, 1.0),
+            Filter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                add(x0, y0, (1.0 - fx) * (1.0 - fy));
+                add(x0 + 1, y0, fx * (1.0 - fy));
+                add(x0, y0 + 1, (1.0 - fx) * fy);
+                add(x0 + 1, y0 + 1, fx * fy);
+            },
+            Filter::Bicubic => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (wx, wy) = (catmull_rom(x - x0), catmull_rom(y - y0));
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                for (j, wy) in wy.iter().enumerate() {
+                    for (i, wx) in wx.iter().enumerate() {
+                        add(x0 + i as i64 - 1, y0 + j as i64 - 1, wx * wy);
+                    }
+                }
 
-        // Compute the gradient of the bump height using nearby points
-        let dx = self.sample_pixel(x_next, y).r - self.sample_pixel(x, y).r;
-        let dy = self.sample_pixel(x, y_next).r - self.sample_pixel(x, y).r;
+                // catmull-rom rings at hard edges, keep it within the four texels it interpolates
+                let (mut low, mut high) = ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]);
+                for (tx, ty) in [(x0, y0), (x0 + 1, y0), (x0, y0 + 1), (x0 + 1, y0 + 1)] {
+                    let t = Self::texel(&mut reader, wrap, size, tx, ty);
+                    for c in 0..3 {
+                        low[c] = low[c].min(t[c]);
+                        high[c] = high[c].max(t[c]);
+                    }
+                }
+                for c in 0..3 {
+                    sum[c] = sum[c].clamp(low[c], high[c]);
+                }
+            },
+        }
+        Color::new(sum[0], sum[1], sum[2], 1.0)
+    }
 
-        Color::new(dx, dy, 0.0, 1.0)
+    // blends the two mip levels around a footprint width given in level 0 texels
+    fn trilinear(&self, texture: &CachedTexture, wrap: Wrap, u: f64, v: f64, width: f64) -> Color {
+        let max_level = (texture.level_count() - 1) as f64;
+        let lod = if width > 1.0 && width.is_finite() {width.log2().min(max_level)} else {0.0};
+        let lower = lod.floor();
+        let t = lod - lower;
+        let a = self.sample_level(texture, wrap, lower as usize, u, v);
+        if t <= 0.0 {
+            return a
+        }
+        let b = self.sample_level(texture, wrap, lower as usize + 1, u, v);
+        Color::new(a.r + (b.r - a.r) * t, a.g + (b.g - a.g) * t, a.b + (b.b - a.b) * t, 1.0)
     }
 
-    pub fn sample_pixel(&self, x: u32, y: u32) -> Color {
-        let pixel = self.image.get_pixel(x, y);
-        if self.srgb {
-            let srgb = Srgb::new(
-            f32::from(pixel[0]),
-            f32::from(pixel[1]),
-            f32::from(pixel[2]),
-            ).into_linear();
-            Color::new(srgb.red.into(), srgb.green.into(), srgb.blue.into(), 1.0)
-        } else {
-            Color::new(pixel[0].into(), pixel[1].into(), pixel[2].into(), 1.0)
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        match self.resolve(u as f64, v as f64) {
+            Some((texture, wrap, u, v)) => self.sample_level(texture, wrap, 0, u, v),
+            None => Color::new(0.0, 0.0, 0.0, 1.0),
         }
     }
-}
 
+    // filtered lookup over a footprint given by its two axes in uv
+    pub fn lookup(&self, u: f64, v: f64, major: Vec2, minor: Vec2) -> Color {
+        let Some((texture, wrap, u, v)) = self.resolve(u, v) else {
+            return Color::new(0.0, 0.0, 0.0, 1.0)
+        };
+        let (width, height) = texture.dimensions(0);
+        let size = width.max(height) as f64;
+        let (mut major, mut minor) = (major, minor);
+        if minor.length() > major.length() {
+            (major, minor) = (minor, major);
+        }
+        match self.mip_filter {
+            MipFilter::None => self.sample_level(texture, wrap, 0, u, v),
+            MipFilter::Trilinear => self.trilinear(texture, wrap, u, v, major.length() as f64 * size),
+            // probes spread along the long axis, each filtered at the short axis' width
+            MipFilter::Anisotropic => {
+                let (major_length, minor_length) = (major.length() as f64, minor.length() as f64);
+                let probes = (major_length / minor_length.max(1e-12)).min(MAX_ANISOTROPY).ceil().max(1.0);
+                let probe_width = major_length / probes * size;
+                let mut sum = [0.0; 3];
+                for i in 0..probes as usize {
+                    let t = (i as f64 + 0.5) / probes - 0.5;
+                    let c = self.trilinear(texture, wrap, u + major.x as f64 * t, v + major.y as f64 * t, probe_width);
+                    sum = [sum[0] + c.r, sum[1] + c.g, sum[2] + c.b];
+                }
+                Color::new(sum[0] / probes, sum[1] / probes, sum[2] / probes, 1.0)
+            },
+        }
+    }
+}
 
+// tiles are found by listing the pattern's directory, a pattern without any is only warned
+// about since its uvs then sample black
+fn udim_tiles(pattern: &str, srgb: bool) -> Result<HashMap<u32, Arc<CachedTexture>>, String> {
+    let path = Path::new(pattern);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().ok_or(format!("{pattern}: no file name"))?.to_string_lossy();
+    let (prefix, suffix) = name.split_once("<UDIM>").ok_or(format!("{pattern}: <UDIM> must be in the file name"))?;
 
+    let mut tiles = HashMap::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("{pattern}: {e}"))? {
+        let file_name = entry.map_err(|e| format!("{pattern}: {e}"))?.file_name();
+        let file_name = file_name.to_string_lossy();
+        let tile = file_name.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            .filter(|digits| digits.len() == 4)
+            .and_then(|digits| digits.parse::<u32>().ok());
+        if let Some(tile) = tile.filter(|tile| *tile >= 1001) {
+            let tile_path = pattern.replace("<UDIM>", &tile.to_string());
+            tiles.insert(tile, texture_cache().get(&tile_path, srgb)?);
+        }
+    }
+    if tiles.is_empty() {
+        eprintln!("{pattern}: no udim tiles found, it will render black");
+    }
+    Ok(tiles)
+}
 
+fn catmull_rom(t: f64) -> [f64; 4] {
+    [
+        t * (-0.5 + t * (1.0 - 0.5 * t)),
+        1.0 + t * t * (-2.5 + 1.5 * t),
+        t * (0.5 + t * (2.0 - 1.5 * t)),
+        t * t * (-0.5 + 0.5 * t),
+    ]
+}
@@ -0,0 +1,441 @@
+use image::{ImageBuffer, DynamicImage, RgbImage, Rgb32FImage};
+use image::codecs::hdr::HdrDecoder;
+use palette::Srgb;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use rayon::prelude::*;
+
+
+const TILE_SIZE: u32 = 64;
+const DEFAULT_BUDGET: usize = 2048 * 1024 * 1024;
+
+static CACHE: OnceLock<TextureCache> = OnceLock::new();
+
+// every texture file is loaded once per path and color space and shared by all its users
+pub fn texture_cache() -> &'static TextureCache {
+    CACHE.get_or_init(|| TextureCache {
+        textures: Mutex::new(HashMap::new()),
+        budget: AtomicUsize::new(DEFAULT_BUDGET),
+        memory: AtomicUsize::new(0),
+        peak: AtomicUsize::new(0),
+        clock: AtomicU64::new(0),
+        counters: Mutex::new(Vec::new()),
+        loads: AtomicU64::new(0),
+        evictions: AtomicU64::new(0),
+        evicting: Mutex::new(()),
+    })
+}
+
+pub struct CacheStats {
+    pub textures: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub loads: u64,
+    pub evictions: u64,
+    pub memory: usize,
+    pub peak: usize,
+}
+
+// tile hits and misses are counted per render thread so lookups never share a counter
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+thread_local! {
+    static COUNTERS: Arc<Counters> = {
+        let counters = Arc::new(Counters::default());
+        texture_cache().counters.lock().unwrap().push(counters.clone());
+        counters
+    };
+}
+
+// a texture's decoded file is kept as its source, and linear 64x64 tiles of every mip level are built
+// from it one at a time as lookups reach them. sources and tiles are dropped least recently used
+// first once memory passes the budget, a dropped source is only decoded again when a tile needs it
+pub struct TextureCache {
+    textures: Mutex<HashMap<(String, bool), Arc<CachedTexture>>>,
+    budget: AtomicUsize,
+    memory: AtomicUsize,
+    peak: AtomicUsize,
+    clock: AtomicU64,
+    counters: Mutex<Vec<Arc<Counters>>>,
+    loads: AtomicU64,
+    evictions: AtomicU64,
+    evicting: Mutex<()>,
+}
+
+impl TextureCache {
+    // only the file's header is read here, so a missing or unreadable file fails at scene setup
+    pub fn get(&self, path: &str, srgb: bool) -> Result<Arc<CachedTexture>, String> {
+        let key = (path.to_string(), srgb);
+        if let Some(texture) = self.textures.lock().unwrap().get(&key) {
+            return Ok(texture.clone())
+        }
+        let (width, height) = image::image_dimensions(path).map_err(|e| format!("{path}: {e}"))?;
+        let texture = Arc::new(CachedTexture::new(path, srgb, width, height));
+        Ok(self.textures.lock().unwrap().entry(key).or_insert(texture).clone())
+    }
+
+    pub fn set_budget(&self, bytes: usize) {
+        self.budget.store(bytes, Ordering::Relaxed);
+        self.evict();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let counters = self.counters.lock().unwrap();
+        CacheStats {
+            textures: self.textures.lock().unwrap().len(),
+            hits: counters.iter().map(|c| c.hits.load(Ordering::Relaxed)).sum(),
+            misses: counters.iter().map(|c| c.misses.load(Ordering::Relaxed)).sum(),
+            loads: self.loads.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            memory: self.memory.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn allocate(&self, bytes: usize) {
+        let memory = self.memory.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(memory, Ordering::Relaxed);
+    }
+
+    // drops the stalest tiles and sources until memory is back under 90% of the budget,
+    // the slack keeps every miss past the budget from rescanning the cache
+    fn evict(&self) {
+        let budget = self.budget.load(Ordering::Relaxed);
+        if self.memory.load(Ordering::Relaxed) <= budget {
+            return
+        }
+        // another thread may have made room while this one waited
+        let _guard = self.evicting.lock().unwrap();
+        if self.memory.load(Ordering::Relaxed) <= budget {
+            return
+        }
+        let target = budget / 10 * 9;
+        let textures: Vec<Arc<CachedTexture>> = self.textures.lock().unwrap().values().cloned().collect();
+        let mut resident: Vec<(u64, &CachedTexture, Option<&Slot>)> = Vec::new();
+        for texture in &textures {
+            if texture.source.lock().unwrap().is_some() {
+                resident.push((texture.source_used.load(Ordering::Relaxed), texture, None));
+            }
+            for slot in texture.slots.iter().filter(|slot| slot.tile.read().unwrap().is_some()) {
+                resident.push((slot.last_used.load(Ordering::Relaxed), texture, Some(slot)));
+            }
+        }
+        resident.sort_by_key(|(last_used, _, _)| *last_used);
+
+        for (_, texture, slot) in resident {
+            if self.memory.load(Ordering::Relaxed) <= target {
+                break
+            }
+            let freed = match slot {
+                Some(slot) => slot.tile.write().unwrap().take().map(|tile| tile.bytes()),
+                None => texture.source.lock().unwrap().take().map(|source| source.bytes()),
+            };
+            if let Some(bytes) = freed {
+                self.memory.fetch_sub(bytes, Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+struct Tile {
+    width: u32,
+    texels: Vec<f32>,
+}
+
+impl Tile {
+    fn bytes(&self) -> usize {
+        self.texels.len() * std::mem::size_of::<f32>()
+    }
+
+    fn texel(&self, x: u32, y: u32) -> [f32; 3] {
+        let i = ((y * self.width + x) * 3) as usize;
+        [self.texels[i], self.texels[i + 1], self.texels[i + 2]]
+    }
+}
+
+struct Slot {
+    tile: RwLock<Option<Arc<Tile>>>,
+    last_used: AtomicU64,
+}
+
+struct LevelLayout {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    first_slot: usize,
+}
+
+// the decoded file, 8 bit images stay 8 bit until their tiles are built
+enum Source {
+    Rgb8(RgbImage),
+    Rgb32F(Rgb32FImage),
+}
+
+impl Source {
+    fn bytes(&self) -> usize {
+        match self {
+            Source::Rgb8(image) => image.as_raw().len(),
+            Source::Rgb32F(image) => image.as_raw().len() * std::mem::size_of::<f32>(),
+        }
+    }
+}
+
+pub struct CachedTexture {
+    pub path: String,
+    pub srgb: bool,
+    levels: Vec<LevelLayout>,
+    slots: Vec<Slot>,
+    source: Mutex<Option<Arc<Source>>>,
+    source_used: AtomicU64,
+    failed: AtomicBool,
+}
+
+impl std::fmt::Debug for CachedTexture {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CachedTexture").field("path", &self.path).field("srgb", &self.srgb).finish()
+    }
+}
+
+impl CachedTexture {
+    fn new(path: &str, srgb: bool, width: u32, height: u32) -> Self {
+        let (mut width, mut height) = (width.max(1), height.max(1));
+        let mut levels = Vec::new();
+        let mut slot_count = 0;
+        loop {
+            let tiles_x = width.div_ceil(TILE_SIZE);
+            levels.push(LevelLayout { width, height, tiles_x, first_slot: slot_count });
+            slot_count += (tiles_x * height.div_ceil(TILE_SIZE)) as usize;
+            if (width, height) == (1, 1) {
+                break
+            }
+            (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        }
+        let slots = (0..slot_count).map(|_| Slot {
+            tile: RwLock::new(None),
+            last_used: AtomicU64::new(0),
+        }).collect();
+        CachedTexture {
+            path: path.to_string(),
+            srgb,
+            levels,
+            slots,
+            source: Mutex::new(None),
+            source_used: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn dimensions(&self, level: usize) -> (u32, u32) {
+        let layout = &self.levels[level];
+        (layout.width, layout.height)
+    }
+
+    // texel access for one lookup, see TexelReader
+    pub fn reader(&self, level: usize) -> TexelReader<'_> {
+        TexelReader { texture: self, level, tile: None, hits: 0, misses: 0 }
+    }
+
+    // the resident tile or a freshly built one, and whether it was a hit
+    fn tile(&self, level: usize, tile_x: u32, tile_y: u32) -> (Arc<Tile>, bool) {
+        let cache = texture_cache();
+        let layout = &self.levels[level];
+        let slot = &self.slots[layout.first_slot + (tile_y * layout.tiles_x + tile_x) as usize];
+
+        // recency only advances on misses, so hits just tag the tile with the current tick
+        let now = cache.clock.load(Ordering::Relaxed);
+        if slot.last_used.load(Ordering::Relaxed) != now {
+            slot.last_used.store(now, Ordering::Relaxed);
+        }
+        if let Some(tile) = slot.tile.read().unwrap().as_ref() {
+            return (tile.clone(), true)
+        }
+
+        let tile = Arc::new(self.build(level, tile_x, tile_y));
+        {
+            let mut resident = slot.tile.write().unwrap();
+            if let Some(existing) = resident.as_ref() {
+                return (existing.clone(), false)
+            }
+            cache.allocate(tile.bytes());
+            *resident = Some(tile.clone());
+        }
+        slot.last_used.store(cache.tick(), Ordering::Relaxed);
+        cache.evict();
+        (tile, false)
+    }
+
+    // level 0 tiles are copied out of the source and linearized, every coarser tile is a 2x2 box
+    // filter of the level below, where odd edges reuse their last row or column
+    fn build(&self, level: usize, tile_x: u32, tile_y: u32) -> Tile {
+        let layout = &self.levels[level];
+        let (x0, y0) = (tile_x * TILE_SIZE, tile_y * TILE_SIZE);
+        let width = TILE_SIZE.min(layout.width - x0);
+        let height = TILE_SIZE.min(layout.height - y0);
+        let mut texels = Vec::with_capacity((width * height * 3) as usize);
+
+        if level == 0 {
+            let Some(source) = self.source() else {
+                texels.resize((width * height * 3) as usize, 0.0);
+                return Tile { width, texels }
+            };
+            for y in y0..y0 + height {
+                for x in x0..x0 + width {
+                    let texel = match source.as_ref() {
+                        Source::Rgb8(image) => {
+                            let lut = srgb_lut();
+                            let pixel = image.get_pixel(x, y);
+                            if self.srgb {
+                                pixel.0.map(|c| lut[c as usize])
+                            } else {
+                                pixel.0.map(|c| c as f32 / 255.0)
+                            }
+                        },
+                        Source::Rgb32F(image) => {
+                            let pixel = image.get_pixel(x, y);
+                            if self.srgb {
+                                let linear = Srgb::new(pixel[0], pixel[1], pixel[2]).into_linear();
+                                [linear.red, linear.green, linear.blue]
+                            } else {
+                                pixel.0
+                            }
+                        },
+                    };
+                    texels.extend_from_slice(&texel);
+                }
+            }
+        } else {
+            let (below_width, below_height) = self.dimensions(level - 1);
+            let mut below = self.reader(level - 1);
+            for y in y0..y0 + height {
+                for x in x0..x0 + width {
+                    let mut sum = [0.0f32; 3];
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let texel = below.texel((2 * x + dx).min(below_width - 1), (2 * y + dy).min(below_height - 1));
+                        for c in 0..3 {
+                            sum[c] += texel[c] * 0.25;
+                        }
+                    }
+                    texels.extend_from_slice(&sum);
+                }
+            }
+        }
+        Tile { width, texels }
+    }
+
+    // decodes the file if its source isn't resident. a file that no longer decodes is reported
+    // once and renders black rather than taking the render down
+    fn source(&self) -> Option<Arc<Source>> {
+        let cache = texture_cache();
+        let mut source = self.source.lock().unwrap();
+        self.source_used.store(cache.tick(), Ordering::Relaxed);
+        if let Some(source) = source.as_ref() {
+            return Some(source.clone())
+        }
+        if self.failed.load(Ordering::Relaxed) {
+            return None
+        }
+        match decode(&self.path, self.srgb) {
+            Ok(decoded) => {
+                let decoded = Arc::new(decoded);
+                cache.loads.fetch_add(1, Ordering::Relaxed);
+                cache.allocate(decoded.bytes());
+                *source = Some(decoded.clone());
+                drop(source);
+                cache.evict();
+                Some(decoded)
+            },
+            Err(e) => {
+                self.failed.store(true, Ordering::Relaxed);
+                eprintln!("{}: {e}, rendering it black", self.path);
+                None
+            },
+        }
+    }
+}
+
+// reads texels of one mip level, holding on to the last tile so a lookup's neighbouring
+// texels take no locks. its hits and misses go to the thread's counters when it is dropped
+pub struct TexelReader<'a> {
+    texture: &'a CachedTexture,
+    level: usize,
+    tile: Option<(u32, u32, Arc<Tile>)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl TexelReader<'_> {
+    pub fn texel(&mut self, x: u32, y: u32) -> [f32; 3] {
+        let (tile_x, tile_y) = (x / TILE_SIZE, y / TILE_SIZE);
+        match &self.tile {
+            Some((tx, ty, _)) if (*tx, *ty) == (tile_x, tile_y) => {},
+            _ => {
+                let (tile, hit) = self.texture.tile(self.level, tile_x, tile_y);
+                if hit {self.hits += 1} else {self.misses += 1}
+                self.tile = Some((tile_x, tile_y, tile));
+            },
+        }
+        let (_, _, tile) = self.tile.as_ref().unwrap();
+        tile.texel(x % TILE_SIZE, y % TILE_SIZE)
+    }
+}
+
+impl Drop for TexelReader<'_> {
+    fn drop(&mut self) {
+        if self.hits + self.misses > 0 {
+            COUNTERS.with(|counters| {
+                counters.hits.fetch_add(self.hits, Ordering::Relaxed);
+                counters.misses.fetch_add(self.misses, Ordering::Relaxed);
+            });
+        }
+    }
+}
+
+// srgb to linear for 8 bit sources
+fn srgb_lut() -> &'static [f32; 256] {
+    static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+    LUT.get_or_init(|| std::array::from_fn(|i| Srgb::new(i as f32 / 255.0, 0.0, 0.0).into_linear().red))
+}
+
+fn decode(file_path: &str, srgb: bool) -> Result<Source, String> {
+    if !srgb && Path::new(file_path).extension().is_some_and(|e| e == "hdr") {
+        let file = std::fs::File::open(file_path).map_err(|e| e.to_string())?;
+        let reader = std::io::BufReader::new(file);
+        let decoder = HdrDecoder::new(reader).map_err(|e| e.to_string())?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr().map_err(|e| e.to_string())?;
+        let buffer_data = pixels.par_chunks(1000)
+            .flat_map(|chunk| {
+                let mut buffer_data = Vec::new();
+                for pixel in chunk {
+                    buffer_data.push(pixel[0]);
+                    buffer_data.push(pixel[1]);
+                    buffer_data.push(pixel[2]);
+                }
+                buffer_data
+            })
+        .collect::<Vec<_>>();
+        let image = ImageBuffer::from_raw(metadata.width, metadata.height, buffer_data).ok_or("bad hdr dimensions")?;
+        return Ok(Source::Rgb32F(image))
+    }
+    Ok(match image::open(file_path).map_err(|e| e.to_string())? {
+        image @ (DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) | DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_)) => {
+            Source::Rgb8(image.into_rgb8())
+        },
+        image => Source::Rgb32F(image.into_rgb32f()),
+    })
+}
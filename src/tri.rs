@@ -1,22 +1,35 @@
 use crate::hit::HitRecord;
 use crate::material::Material;
+use crate::shader::ShadingPoint;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use crate::vec2::Vec2;
+use crate::mat3::Mat3;
 use crate::aabb::Aabb;
 use crate::color::Color;
 use crate::texture::TextureMap;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 
 #[derive(Debug, Clone)]
 pub struct Tri {
     pub vertices: Vec<Vec3>,
+    pub object_vertices: Vec<Vec3>,
+    pub object_to_world: Mat3,
     pub normals: Vec<Vec3>,
     pub uvs: Vec<Vec2>,
     pub area: f64,
+    // per corner tangents and bitangent signs, the bitangent is sign * normal x tangent
+    pub tangents: Vec<Vec3>,
+    pub bitangent_signs: Vec<f64>,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub dndu: Vec3,
+    pub dndv: Vec3,
     pub material: Arc<Material>,
     pub smooth: bool,
+    pub object_id: usize,
 }
 
 impl Tri {
@@ -24,14 +37,58 @@ impl Tri {
         let a = Vec3::cross(&(vertices[1]-vertices[0]), &(vertices[2]-vertices[0]));
         let area = (a.x.abs().powf(2.0) + a.y.abs().powf(2.0) + a.z.abs().powf(2.0)).sqrt();
 
+        // dP/du and dP/dv from the uv layout, falls back to an arbitrary frame without usable uvs
+        let (dp1, dp2) = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+        let (duv1, duv2) = (uvs[1] - uvs[0], uvs[2] - uvs[0]);
+        let det = (duv1.x * duv2.y - duv2.x * duv1.y) as f64;
+        let derivatives = |d1: Vec3, d2: Vec3| if det.abs() > 1e-12 {
+            ((d1 * duv2.y as f64 - d2 * duv1.y as f64) / det, (d2 * duv1.x as f64 - d1 * duv2.x as f64) / det)
+        } else {
+            (Vec3::zeros(), Vec3::zeros())
+        };
+        let (dpdu, dpdv) = derivatives(dp1, dp2);
+        // dN/du and dN/dv of the interpolated normal, zero on flat shaded tris
+        let (dndu, dndv) = if smooth {derivatives(normals[1] - normals[0], normals[2] - normals[0])} else {(Vec3::zeros(), Vec3::zeros())};
+        let tangent = if det.abs() > 1e-12 {dpdu.normalize()} else {a.normalize().tangent_bitangent().0};
+        let sign = if a.cross(&dpdu).dot(&dpdv) < 0.0 {-1.0} else {1.0};
+
         Tri {
+            object_vertices: vertices.clone(),
+            object_to_world: Mat3::identity(),
             vertices,
             normals,
             uvs,
             area,
+            tangents: vec![tangent; 3],
+            bitangent_signs: vec![sign; 3],
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
             material,
             smooth,
+            object_id: 0,
+        }
+    }
+
+    // object space is the mesh before its transform. the transform's linear part maps the
+    // object triangle's edges and normal onto the world triangle's, exact for rotation and scale
+    pub fn set_object_vertices(&mut self, object_vertices: Vec<Vec3>) {
+        let frame = |v: &[Vec3]| {
+            let (e1, e2) = (v[1] - v[0], v[2] - v[0]);
+            let n = e1.cross(&e2);
+            let area = n.length();
+            if area < 1e-20 {
+                return None
+            }
+            Some(Mat3::from_columns(e1, e2, n / area.sqrt()))
+        };
+        if let (Some(world), Some(object)) = (frame(&self.vertices), frame(&object_vertices)) {
+            if let Some(inverse) = object.inverse() {
+                self.object_to_world = world * inverse;
+            }
         }
+        self.object_vertices = object_vertices;
     }
 
     pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> (bool, Option<HitRecord>) {
@@ -67,15 +124,35 @@ impl Tri {
                     normal = (&edge1).cross(&edge2).normalize();
                 }
                 let front_face = normal.dot(&r.direction) < 0.0;
-                return (true,
-                Some(HitRecord {
+
+                // interpolated tangent made orthogonal to the shading normal, back faces flip the whole frame
+                let tangent = (self.tangents[0] * (1.0 - u - v)) + (self.tangents[1] * u) + (self.tangents[2] * v);
+                let mut tangent = tangent - normal * normal.dot(&tangent);
+                tangent = if tangent.length_squared() > 1e-12 {tangent.normalize()} else {normal.tangent_bitangent().0};
+                let bitangent = normal.cross(&tangent) * self.bitangent_signs[0];
+                let flip = if front_face {1.0} else {-1.0};
+                let object_point = (self.object_vertices[0] * (1.0 - u - v)) + (self.object_vertices[1] * u) + (self.object_vertices[2] * v);
+                let rec = HitRecord {
                     t,
                     point: p,
-                    normal: if front_face {normal} else {-normal},
+                    object_point,
+                    object_to_world: self.object_to_world,
+                    normal: normal * flip,
+                    tangent: tangent * flip,
+                    bitangent: bitangent * flip,
+                    dpdu: self.dpdu,
+                    dpdv: self.dpdv,
+                    dndu: self.dndu,
+                    dndv: self.dndv,
                     uv,
                     front_face,
                     material: self.material.clone(),
-                }));                
+                    object_id: self.object_id,
+                };
+                if !self.material.opaque() && self.material.cutout(&ShadingPoint::new(&rec, r)) {
+                    return (false, None);
+                }
+                return (true, Some(rec));
             }
         } else {
             return (false, None);
@@ -83,6 +160,11 @@ impl Tri {
         (false, None)
     }
 
+    pub fn set_tangents(&mut self, tangents: Vec<Vec3>, bitangent_signs: Vec<f64>) {
+        self.tangents = tangents;
+        self.bitangent_signs = bitangent_signs;
+    }
+
     pub fn bounding_box(&self, time0: f64, time1: f64) -> Aabb {
         let pad = 0.001;
         let (v0, v1, v2) = (self.vertices[0], self.vertices[1], self.vertices[2]);
@@ -98,3 +180,49 @@ impl Tri {
     }
 }
 
+// mikktspace style tangents for a mesh. corners sharing a position, normal, uv and handedness are welded,
+// and each sums its faces' dP/du projected onto the corner normal, weighted by the corner angle
+pub fn generate_tangents(tris: &mut [Tri]) {
+    type Key = ([u64; 3], [u64; 3], [u32; 2], bool);
+    let key = |tri: &Tri, corner: usize| -> Key {
+        let (p, n, uv) = (tri.vertices[corner], tri.normals[corner], tri.uvs[corner]);
+        (
+            [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()],
+            [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()],
+            [uv.x.to_bits(), uv.y.to_bits()],
+            tri.bitangent_signs[corner] < 0.0,
+        )
+    };
+
+    let mut sums: HashMap<Key, Vec3> = HashMap::new();
+    for tri in tris.iter() {
+        if tri.dpdu.length_squared() < 1e-24 {
+            continue
+        }
+        for corner in 0..3 {
+            let p = tri.vertices[corner];
+            let (e1, e2) = (tri.vertices[(corner + 1) % 3] - p, tri.vertices[(corner + 2) % 3] - p);
+            if e1.length_squared() < 1e-24 || e2.length_squared() < 1e-24 {
+                continue
+            }
+            let angle = e1.normalize().dot(&e2.normalize()).clamp(-1.0, 1.0).acos();
+            let n = tri.normals[corner];
+            let projected = tri.dpdu - n * n.dot(&tri.dpdu);
+            if projected.length_squared() < 1e-24 {
+                continue
+            }
+            let sum = sums.entry(key(tri, corner)).or_insert(Vec3::zeros());
+            *sum = *sum + projected.normalize() * angle;
+        }
+    }
+
+    for tri in tris.iter_mut() {
+        for corner in 0..3 {
+            if let Some(sum) = sums.get(&key(tri, corner)) {
+                if sum.length_squared() > 1e-24 {
+                    tri.tangents[corner] = sum.normalize();
+                }
+            }
+        }
+    }
+}
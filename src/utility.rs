@@ -11,7 +11,7 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
 
 pub fn random_float() -> f64 {
     let mut rng = rand::thread_rng();
-    rng.gen_range(0..100) as f64 / 100.0
+    rng.gen::<f64>()
 }
 
 pub fn random_range(min: f64, max: f64) -> f64 {